@@ -1,11 +1,214 @@
+use crate::{
+    Error,
+    config::{self, Config},
+    http::Url,
+    tee::ProgressTarget,
+    transport::Transport,
+};
+use std::{
+    env,
+    io::{self, IsTerminal},
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+/// How long progress may stand still before the bar shows a stalled warning.
+const DEFAULT_STALL_AFTER: Duration = Duration::from_secs(30);
+
+/// Shortest time between two redraws of the progress bar.
+const DEFAULT_REFRESH: Duration = Duration::from_secs(1);
+
+/// Percent of progress between two webhook progress events.
+const DEFAULT_WEBHOOK_EVERY: f64 = 10.0;
+
+/// ffmpeg binary used unless `--ffmpeg` says otherwise, looked up in `PATH`.
+pub const DEFAULT_FFMPEG: &str = "ffmpeg";
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum ColorMode {
+    Auto,
+    Always,
+    Never,
+}
+
+impl ColorMode {
+    /// Whether forwarded ffmpeg output should be colored.
+    pub fn enabled(self) -> bool {
+        match self {
+            ColorMode::Auto => io::stderr().is_terminal(),
+            ColorMode::Always => true,
+            ColorMode::Never => false,
+        }
+    }
+}
+
+/// What to do when an output file already exists.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Overwrite {
+    /// Let ffmpeg ask, neither `-y` nor `-n` is passed.
+    Ask,
+    /// Pass `-y`.
+    Always,
+    /// Pass `-n`.
+    Never,
+}
+
+#[derive(Clone)]
 pub struct FfmpegArgs {
+    /// Path or name of the ffmpeg binary.
+    pub ffmpeg: String,
+    /// Report the resolved ffmpeg binary and version before running it.
+    pub verbose: bool,
+    /// Print the ffmpeg command instead of running it.
+    pub dry_run: bool,
     pub ss: Option<f64>,
     pub to: Option<f64>,
     pub t: Option<f64>,
     pub clean: bool,
+    pub per_output: bool,
+    pub color: ColorMode,
+    pub pretty: bool,
+    pub no_keys: bool,
+    /// Shortest time between two redraws of the progress bar.
+    pub refresh: Duration,
+    /// Run ffmpeg twice, with `-pass 1` to the null muxer and then `-pass 2`.
+    pub two_pass: bool,
+    /// Probe the outputs with ffprobe once ffmpeg succeeded.
+    pub verify: bool,
+    pub stall_after: Duration,
+    pub stall_timeout: Option<Duration>,
+    pub timeout: Option<Duration>,
+    pub log_file: Option<PathBuf>,
+    pub log_max_size: Option<u64>,
+    /// How often to rerun ffmpeg after a transient failure.
+    pub retries: u32,
+    /// Shell commands run when ffmpeg succeeded, failed, or in either case.
+    pub on_success: Option<String>,
+    pub on_failure: Option<String>,
+    pub on_finish: Option<String>,
+    /// `http://` url receiving JSON events about the run.
+    pub webhook: Option<String>,
+    /// Percent of progress between two webhook progress events.
+    pub webhook_every: f64,
+    /// Address serving the status page and JSON while ffmpeg runs.
+    pub listen: Option<SocketAddr>,
+    /// `-progress` given by the user, which receives a copy of the progress.
+    pub progress_target: Option<ProgressTarget>,
+    /// How ffpb reads the progress, see [`Transport::for_args`].
+    pub progress_via: Option<Transport>,
+    pub outputs: Vec<String>,
     pub args: Vec<String>,
 }
 
+impl FfmpegArgs {
+    /// ffprobe next to the ffmpeg binary if there is one, otherwise from `PATH`.
+    pub fn ffprobe(&self) -> String {
+        let ffmpeg = Path::new(&self.ffmpeg);
+        let name = format!("ffprobe{}", env::consts::EXE_SUFFIX);
+        match ffmpeg.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() && dir.join(&name).is_file() => {
+                dir.join(name).to_string_lossy().to_string()
+            }
+            _ => "ffprobe".to_string(),
+        }
+    }
+}
+
+/// ffmpeg options which do not consume the following argument. ffmpeg also
+/// accepts each of them with a `no` prefix, e.g. `-nostdin`.
+const FLAG_OPTIONS: &[&str] = &[
+    "-accurate_seek",
+    "-an",
+    "-autorotate",
+    "-autoscale",
+    "-benchmark",
+    "-benchmark_all",
+    "-bitexact",
+    "-copy_unknown",
+    "-copyinkf",
+    "-copyts",
+    "-debug_ts",
+    "-dn",
+    "-dump",
+    "-find_stream_info",
+    "-fix_sub_duration",
+    "-fix_sub_duration_heartbeat",
+    "-hex",
+    "-hide_banner",
+    "-ignore_unknown",
+    "-n",
+    "-print_graphs",
+    "-qphist",
+    "-re",
+    "-recast_media",
+    "-report",
+    "-shortest",
+    "-sn",
+    "-start_at_zero",
+    "-stats",
+    "-stdin",
+    "-vn",
+    "-vstats",
+    "-xerror",
+    "-y",
+];
+
+/// Whether an ffmpeg option does not consume the following argument.
+fn is_flag_option(arg: &str) -> bool {
+    FLAG_OPTIONS.contains(&arg)
+        || arg
+            .strip_prefix("-no")
+            .is_some_and(|flag| FLAG_OPTIONS.contains(&format!("-{flag}").as_str()))
+}
+
+/// Find output urls, i.e. positional arguments not consumed by an option.
+fn find_outputs(args: &[String]) -> Vec<String> {
+    output_positions(args)
+        .into_iter()
+        .map(|i| args[i].clone())
+        .collect()
+}
+
+/// Whether an output url names a file on disk rather than a pipe or a
+/// network destination.
+pub fn is_local_file(output: &str) -> bool {
+    output != "-" && !output.starts_with("pipe:") && !output.contains("://")
+}
+
+/// Whether ffmpeg writes this output to its stdout.
+pub fn is_stdout(output: &str) -> bool {
+    matches!(output, "-" | "pipe:" | "pipe:1")
+}
+
+/// Indices of the output urls in `args`.
+///
+/// If an option ffpb does not know as a flag swallowed every output, falls
+/// back to the last argument, which ffmpeg always takes as an output.
+pub fn output_positions(args: &[String]) -> Vec<usize> {
+    let mut positions = Vec::new();
+    let mut last_value_of = None;
+    let mut iter = args.iter().enumerate();
+
+    while let Some((i, arg)) = iter.next() {
+        if arg == "-" || !arg.starts_with('-') {
+            positions.push(i);
+        } else if !is_flag_option(arg) && iter.next().is_some() {
+            last_value_of = Some(arg.as_str());
+        }
+    }
+
+    if positions.is_empty()
+        && !matches!(last_value_of, None | Some("-i"))
+        && let Some(last) = args.last()
+        && (last == "-" || !last.starts_with('-'))
+    {
+        positions.push(args.len() - 1);
+    }
+
+    positions
+}
+
 pub fn parse_time(s: &str) -> Option<f64> {
     let parts = s.split(':').collect::<Vec<_>>();
     match parts.len() {
@@ -25,10 +228,242 @@ pub fn parse_time(s: &str) -> Option<f64> {
     }
 }
 
-pub fn parse_args(args: &[String]) -> FfmpegArgs {
+fn parse_duration_option(name: &str, value: &str) -> Result<Duration, Error> {
+    parse_time(value)
+        .filter(|secs| *secs >= 0.0)
+        .map(Duration::from_secs_f64)
+        .ok_or_else(|| Error::InvalidOption(format!("invalid duration for {name}: {value}")))
+}
+
+/// Parse a byte size like `1048576`, `512K`, `10M` or `1G`.
+fn parse_size_option(name: &str, value: &str) -> Result<u64, Error> {
+    let upper = value.trim().to_ascii_uppercase();
+    let upper = upper.strip_suffix('B').unwrap_or(&upper);
+    let (num, multiplier) = match upper.char_indices().last() {
+        Some((i, 'K')) => (&upper[..i], 1024),
+        Some((i, 'M')) => (&upper[..i], 1024 * 1024),
+        Some((i, 'G')) => (&upper[..i], 1024 * 1024 * 1024),
+        _ => (upper, 1),
+    };
+
+    num.trim()
+        .parse::<u64>()
+        .ok()
+        .filter(|n| *n > 0)
+        .map(|n| n * multiplier)
+        .ok_or_else(|| Error::InvalidOption(format!("invalid size for {name}: {value}")))
+}
+
+/// ffpb options which are switched on by their presence.
+pub const SWITCH_OPTIONS: &[&str] = &[
+    "--clean",
+    "--dry-run",
+    "--no-keys",
+    "--per-output",
+    "--pretty",
+    "--two-pass",
+    "--verbose",
+    "--verify",
+];
+
+/// ffpb options which take a value, either as `--name value` or `--name=value`.
+pub const VALUE_OPTIONS: &[&str] = &[
+    "--color",
+    "--ffmpeg",
+    "--listen",
+    "--log-dir",
+    "--log-file",
+    "--log-max-size",
+    "--on-failure",
+    "--on-finish",
+    "--on-success",
+    "--overwrite",
+    "--preset",
+    "--profile",
+    "--progress-via",
+    "--refresh",
+    "--retries",
+    "--stall-after",
+    "--stall-timeout",
+    "--timeout",
+    "--webhook",
+    "--webhook-every",
+];
+
+/// Separate ffpb's own `--` options (with their values) from other arguments.
+pub fn split_ffpb_options(args: &[String]) -> (Vec<String>, Vec<String>) {
+    let mut options = Vec::new();
+    let mut rest = Vec::new();
+    let mut iter = args.iter();
+
+    while let Some(arg) = iter.next() {
+        if !arg.starts_with("--") {
+            rest.push(arg.clone());
+            continue;
+        }
+        options.push(arg.clone());
+        if VALUE_OPTIONS.contains(&arg.as_str())
+            && let Some(value) = iter.next()
+        {
+            options.push(value.clone());
+        }
+    }
+
+    (options, rest)
+}
+
+/// Whether a switch given as `--<switch>` or `--<switch>=<value>` is on.
+/// The value may be `true`/`false`, `1`/`0`, `yes`/`no` or `on`/`off`.
+pub fn parse_switch(name: &str, value: Option<&str>) -> Result<bool, Error> {
+    match value.map(str::to_ascii_lowercase).as_deref() {
+        None | Some("1" | "true" | "yes" | "on") => Ok(true),
+        Some("0" | "false" | "no" | "off") => Ok(false),
+        Some(_) => Err(Error::InvalidOption(format!(
+            "invalid value for {name}: {} (expected true or false)",
+            value.unwrap_or_default()
+        ))),
+    }
+}
+
+/// Name of the environment variable setting an ffpb option, e.g.
+/// `FFPB_LOG_FILE` for `--log-file`. Names starting with `FFPB_HOOK_` are
+/// left to the variables hooks see.
+pub fn env_var_name(option: &str) -> String {
+    format!(
+        "FFPB_{}",
+        option
+            .trim_start_matches('-')
+            .replace('-', "_")
+            .to_uppercase()
+    )
+}
+
+/// Split a command line into words, honouring single quotes, double quotes
+/// and backslash escapes like a POSIX shell.
+pub fn split_shell(line: &str) -> Result<Vec<String>, Error> {
+    let mut words = Vec::new();
+    let mut word = String::new();
+    let mut in_word = false;
+    let mut chars = line.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\'' => {
+                in_word = true;
+                loop {
+                    match chars.next() {
+                        Some('\'') => break,
+                        Some(c) => word.push(c),
+                        None => {
+                            return Err(Error::InvalidOption(format!("unclosed quote in: {line}")));
+                        }
+                    }
+                }
+            }
+            '"' => {
+                in_word = true;
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some(c @ ('"' | '\\' | '$' | '`')) => word.push(c),
+                            Some(c) => {
+                                word.push('\\');
+                                word.push(c);
+                            }
+                            None => break,
+                        },
+                        Some(c) => word.push(c),
+                        None => {
+                            return Err(Error::InvalidOption(format!("unclosed quote in: {line}")));
+                        }
+                    }
+                }
+            }
+            '\\' => {
+                in_word = true;
+                if let Some(c) = chars.next() {
+                    word.push(c);
+                }
+            }
+            c if c.is_whitespace() => {
+                if in_word {
+                    words.push(std::mem::take(&mut word));
+                    in_word = false;
+                }
+            }
+            c => {
+                in_word = true;
+                word.push(c);
+            }
+        }
+    }
+    if in_word {
+        words.push(word);
+    }
+
+    Ok(words)
+}
+
+/// Quote a command line so it can be pasted into a POSIX shell.
+pub fn shell_join(program: &str, args: &[String]) -> String {
+    std::iter::once(program)
+        .chain(args.iter().map(String::as_str))
+        .map(|arg| {
+            let safe = !arg.is_empty()
+                && arg
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || "-_./:=,+%@".contains(c));
+            if safe {
+                arg.to_string()
+            } else {
+                format!("'{}'", arg.replace('\'', "'\\''"))
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Insert the arguments of `--preset` before the first output, as they are
+/// output options in most cases, with `{input}` and `{output}` replaced by
+/// the first input and output.
+fn expand_preset(args: &mut Vec<String>, preset: Vec<String>) -> Result<(), Error> {
+    let input = args.windows(2).find(|w| w[0] == "-i").map(|w| w[1].clone());
+    let position = output_positions(args).first().copied();
+    let output = position.map(|i| args[i].clone());
+
+    let mut expanded = Vec::with_capacity(preset.len());
+    for mut arg in preset {
+        for (placeholder, value) in [("{input}", &input), ("{output}", &output)] {
+            if arg.contains(placeholder) {
+                let value = value.as_ref().ok_or_else(|| {
+                    Error::InvalidOption(format!(
+                        "--preset uses {placeholder} but the command has none"
+                    ))
+                })?;
+                arg = arg.replace(placeholder, value);
+            }
+        }
+        expanded.push(arg);
+    }
+
+    let position = position.unwrap_or(args.len());
+    args.splice(position..position, expanded);
+    Ok(())
+}
+
+pub fn parse_args(args: &[String]) -> Result<FfmpegArgs, Error> {
+    let config = Config::load(config::selected_profile(args).as_deref())?;
+    parse_configured(args, &config)
+}
+
+/// Parse `args` on top of the options from `config`.
+fn parse_configured(args: &[String], config: &Config) -> Result<FfmpegArgs, Error> {
+    // Configured options go first, so the command line takes precedence.
+    let mut rejoined = config.options();
+
     // PowerShell splits args like `-c:v` into `["-c:", "v"]`.
     // Rejoin them before parsing.
-    let mut rejoined = Vec::with_capacity(args.len());
     let mut i = 0;
 
     while i < args.len() {
@@ -41,13 +476,239 @@ pub fn parse_args(args: &[String]) -> FfmpegArgs {
         }
     }
 
-    let args = rejoined;
+    let mut ffmpeg = DEFAULT_FFMPEG.to_string();
+    let mut verbose = false;
+    let mut dry_run = false;
+    let mut presets = Vec::new();
+    let mut clean = false;
+    let mut per_output = false;
+    let mut color = ColorMode::Auto;
+    let mut pretty = false;
+    let mut no_keys = false;
+    let mut refresh = DEFAULT_REFRESH;
+    let mut two_pass = false;
+    let mut verify = false;
+    let mut overwrite = None;
+    let mut stall_after = DEFAULT_STALL_AFTER;
+    let mut stall_timeout = None;
+    let mut timeout = None;
+    let mut log_file = None;
+    let mut log_dir = None;
+    let mut log_max_size = None;
+    let mut retries = 0;
+    let mut on_success = None;
+    let mut on_failure = None;
+    let mut on_finish = None;
+    let mut webhook = None;
+    let mut webhook_every = DEFAULT_WEBHOOK_EVERY;
+    let mut listen = None;
+    let mut progress_via = None;
+
+    // Pull out ffpb's own options, everything else is forwarded to ffmpeg.
+    let mut args = Vec::with_capacity(rejoined.len());
+    let mut iter = rejoined.into_iter();
+    while let Some(arg) = iter.next() {
+        if !arg.starts_with("--") {
+            args.push(arg);
+            continue;
+        }
+
+        let (name, inline_value) = match arg.split_once('=') {
+            Some((name, value)) => (name.to_string(), Some(value.to_string())),
+            None => (arg.clone(), None),
+        };
+        let mut value = || {
+            inline_value
+                .clone()
+                .or_else(|| iter.next())
+                .ok_or_else(|| Error::InvalidOption(format!("{name} requires a value")))
+        };
+        // Like ffmpeg options, the last occurrence of a switch wins.
+        let switch = || parse_switch(&name, inline_value.as_deref());
+
+        match name.as_str() {
+            "--ffmpeg" => {
+                ffmpeg = value()?;
+                if ffmpeg.is_empty() {
+                    return Err(Error::InvalidOption("--ffmpeg requires a value".into()));
+                }
+            }
+            "--verbose" => {
+                verbose = switch()?;
+            }
+            "--dry-run" => {
+                dry_run = switch()?;
+            }
+            "--preset" => {
+                presets.push(value()?);
+            }
+            // Already applied by loading the configuration.
+            "--profile" => {
+                value()?;
+            }
+            "--clean" => {
+                clean = switch()?;
+            }
+            "--per-output" => {
+                per_output = switch()?;
+            }
+            "--color" => {
+                color = match value()?.as_str() {
+                    "auto" => ColorMode::Auto,
+                    "always" => ColorMode::Always,
+                    "never" => ColorMode::Never,
+                    other => {
+                        return Err(Error::InvalidOption(format!(
+                            "invalid value for --color: {other} (expected auto, always or never)"
+                        )));
+                    }
+                };
+            }
+            "--pretty" => {
+                pretty = switch()?;
+            }
+            "--no-keys" => {
+                no_keys = switch()?;
+            }
+            "--refresh" => {
+                refresh = parse_duration_option(&name, &value()?)?;
+            }
+            "--two-pass" => {
+                two_pass = switch()?;
+            }
+            "--verify" => {
+                verify = switch()?;
+            }
+            "--overwrite" => {
+                overwrite = Some(match value()?.as_str() {
+                    "ask" => Overwrite::Ask,
+                    "always" => Overwrite::Always,
+                    "never" => Overwrite::Never,
+                    other => {
+                        return Err(Error::InvalidOption(format!(
+                            "invalid value for --overwrite: {other} (expected ask, always or never)"
+                        )));
+                    }
+                });
+            }
+            "--stall-after" => {
+                stall_after = parse_duration_option(&name, &value()?)?;
+            }
+            "--stall-timeout" => {
+                stall_timeout = Some(parse_duration_option(&name, &value()?)?);
+            }
+            "--timeout" => {
+                timeout = Some(parse_duration_option(&name, &value()?)?);
+            }
+            "--log-file" => {
+                log_file = Some(PathBuf::from(value()?));
+            }
+            "--log-dir" => {
+                log_dir = Some(PathBuf::from(value()?));
+            }
+            "--log-max-size" => {
+                log_max_size = Some(parse_size_option(&name, &value()?)?);
+            }
+            "--retries" => {
+                let value = value()?;
+                retries = value.parse().map_err(|_| {
+                    Error::InvalidOption(format!("invalid value for --retries: {value}"))
+                })?;
+            }
+            "--on-success" => {
+                on_success = Some(value()?);
+            }
+            "--on-failure" => {
+                on_failure = Some(value()?);
+            }
+            "--on-finish" => {
+                on_finish = Some(value()?);
+            }
+            "--webhook" => {
+                let value = value()?;
+                if Url::parse(&value).is_none() {
+                    return Err(Error::InvalidOption(format!(
+                        "invalid url for --webhook: {value} (expected http://host[:port]/path)"
+                    )));
+                }
+                webhook = Some(value);
+            }
+            "--webhook-every" => {
+                let value = value()?;
+                webhook_every = value
+                    .trim_end_matches('%')
+                    .parse()
+                    .ok()
+                    .filter(|p| *p > 0.0 && *p <= 100.0)
+                    .ok_or_else(|| {
+                        Error::InvalidOption(format!(
+                            "invalid percent for --webhook-every: {value}"
+                        ))
+                    })?;
+            }
+            "--listen" => {
+                let value = value()?;
+                listen = Some(value.parse().map_err(|_| {
+                    Error::InvalidOption(format!(
+                        "invalid address for --listen: {value} (expected host:port, e.g. 127.0.0.1:8080)"
+                    ))
+                })?);
+            }
+            "--progress-via" => {
+                let value = value()?;
+                progress_via = Some(Transport::parse(&value).ok_or_else(|| {
+                    Error::InvalidOption(format!(
+                        "invalid transport for --progress-via: {value} (expected {})",
+                        if cfg!(unix) {
+                            "pipe, unix, tcp or stdout"
+                        } else {
+                            "tcp or stdout"
+                        }
+                    ))
+                })?);
+            }
+            _ => args.push(arg),
+        }
+    }
+
+    if !presets.is_empty() {
+        let mut expanded = Vec::new();
+        for name in &presets {
+            expanded.extend(config.preset(name)?);
+        }
+        expand_preset(&mut args, expanded)?;
+    }
+
+    // --overwrite takes precedence over any -y/-n given to ffmpeg.
+    if let Some(overwrite) = overwrite {
+        args.retain(|a| a != "-y" && a != "-n");
+        match overwrite {
+            Overwrite::Ask => {}
+            Overwrite::Always => args.insert(0, "-y".to_string()),
+            Overwrite::Never => args.insert(0, "-n".to_string()),
+        }
+    }
+
+    // ffpb always reads the progress itself (see `transport`), a `-progress`
+    // given by the user gets a copy of every line. Like ffmpeg, the last one
+    // wins.
+    let mut progress_target = None;
+    while let Some(i) = args.iter().position(|a| a == "-progress") {
+        let url = args
+            .get(i + 1)
+            .cloned()
+            .ok_or_else(|| Error::InvalidOption("missing value for -progress".into()))?;
+        progress_target = Some(ProgressTarget::parse(&url).ok_or_else(|| {
+            Error::InvalidOption(format!(
+                "unsupported -progress target: {url} (expected a file, pipe:1, pipe:2, tcp://host:port or unix:path)"
+            ))
+        })?);
+        args.drain(i..=i + 1);
+    }
 
     let mut ss = None;
     let mut to = None;
     let mut t = None;
-    let mut clean = false;
-    let mut has_progress = false;
     let mut has_nostats = false;
 
     let mut iter = args.iter().peekable();
@@ -68,38 +729,188 @@ pub fn parse_args(args: &[String]) -> FfmpegArgs {
                     t = parse_time(val);
                 }
             }
-            "-progress" => {
-                has_progress = true;
-            }
             "-nostats" => {
                 has_nostats = true;
             }
-            "--clean" => {
-                clean = true;
-            }
             _ => {}
         }
     }
 
-    let mut args = args
-        .iter()
-        .filter(|a| *a != "--clean")
-        .cloned()
-        .collect::<Vec<_>>();
-
-    if !has_progress {
-        args.push("-progress".to_string());
-        args.push("pipe:1".to_string());
+    let outputs = find_outputs(&args);
+    if progress_via == Some(Transport::Stdout) && outputs.iter().any(|o| is_stdout(o)) {
+        return Err(Error::InvalidOption(
+            "--progress-via stdout can't be used when ffmpeg writes media to stdout".into(),
+        ));
     }
+
     if !has_nostats {
         args.push("-nostats".to_string());
     }
 
-    FfmpegArgs {
+    // An explicit --log-file wins over a log directory.
+    if log_file.is_none()
+        && let Some(dir) = log_dir
+    {
+        log_file = Some(dir.join(log_name(&outputs)));
+    }
+
+    Ok(FfmpegArgs {
+        ffmpeg,
+        verbose,
+        dry_run,
         ss,
         to,
         t,
         clean,
+        per_output,
+        color,
+        pretty,
+        no_keys,
+        refresh,
+        two_pass,
+        verify,
+        stall_after,
+        stall_timeout,
+        timeout,
+        log_file,
+        log_max_size,
+        retries,
+        on_success,
+        on_failure,
+        on_finish,
+        webhook,
+        webhook_every,
+        listen,
+        progress_target,
+        progress_via,
+        outputs,
         args,
+    })
+}
+
+/// Name of the log file written to `--log-dir`, after the first output
+/// file, e.g. `out.mp4.log`.
+fn log_name(outputs: &[String]) -> String {
+    let name = outputs
+        .iter()
+        .filter(|output| is_local_file(output))
+        .find_map(|output| Path::new(output).file_name())
+        .map_or_else(|| "ffpb".into(), |name| name.to_string_lossy());
+    format!("{name}.log")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn words(line: &str) -> Vec<String> {
+        line.split_whitespace().map(String::from).collect()
+    }
+
+    fn outputs(line: &str) -> Vec<String> {
+        find_outputs(&words(line))
+    }
+
+    #[test]
+    fn finds_outputs() {
+        assert_eq!(outputs("-i in.mkv -c:v libx264 out.mp4"), ["out.mp4"]);
+        assert_eq!(
+            outputs("-y -i in.mkv -map 0:v a.mp4 -an -map 0:v b.mp4 -f null -"),
+            ["a.mp4", "b.mp4", "-"]
+        );
+        assert_eq!(
+            outputs("-hide_banner -nostdin -i in.mkv -shortest out.mp4"),
+            ["out.mp4"]
+        );
+        assert_eq!(
+            outputs("-i in.mkv -vstats -print_graphs -c copy out.mp4"),
+            ["out.mp4"]
+        );
+        assert_eq!(outputs("-i in.mkv -noautorotate -f mp4 pipe:1"), ["pipe:1"]);
+        assert!(outputs("-i in.mkv").is_empty());
+    }
+
+    #[test]
+    fn falls_back_to_the_last_argument() {
+        assert_eq!(outputs("-i in.mkv -unknown_flag out.mp4"), ["out.mp4"]);
+        assert!(outputs("-i in.mkv -unknown_flag").is_empty());
+        assert_eq!(output_positions(&words("-version")), Vec::<usize>::new());
+    }
+
+    fn parse(env: &[(&str, &str)], line: &str) -> Result<FfmpegArgs, Error> {
+        let mut config = Config::default();
+        config.apply_env(|name| {
+            env.iter()
+                .find(|(var, _)| *var == name)
+                .map(|(_, value)| value.to_string())
+        })?;
+        parse_configured(&words(line), &config)
+    }
+
+    #[test]
+    fn command_line_overrides_switches() {
+        let env = [("FFPB_CLEAN", "1"), ("FFPB_VERIFY", "yes")];
+        let args = parse(&env, "--clean=false -i in.mkv out.mp4").unwrap();
+        assert!(!args.clean);
+        assert!(args.verify);
+        assert_eq!(args.args, ["-i", "in.mkv", "out.mp4", "-nostats"]);
+
+        let args = parse(&[("FFPB_CLEAN", "0")], "--clean -i in.mkv out.mp4").unwrap();
+        assert!(args.clean);
+        let args = parse(&[], "--pretty=off --pretty -i in.mkv --pretty=0 out.mp4").unwrap();
+        assert!(!args.pretty);
+        assert!(parse(&[], "--clean=maybe -i in.mkv out.mp4").is_err());
+    }
+
+    #[test]
+    fn names_logs_in_the_log_dir() {
+        let env = [("FFPB_LOG_DIR", "logs")];
+        let args = parse(&env, "-i in.mkv -f null - sub/out.mp4").unwrap();
+        assert_eq!(args.log_file, Some(PathBuf::from("logs/out.mp4.log")));
+        let args = parse(&env, "-i in.mkv -f mpegts pipe:1").unwrap();
+        assert_eq!(args.log_file, Some(PathBuf::from("logs/ffpb.log")));
+        let args = parse(&env, "--log-file=x.log -i in.mkv out.mp4").unwrap();
+        assert_eq!(args.log_file, Some(PathBuf::from("x.log")));
+    }
+
+    #[test]
+    fn splits_like_a_shell() {
+        assert_eq!(
+            split_shell(r#"-i 'my input.mkv' -vf "scale=1280:-2" out\ 1.mp4"#).unwrap(),
+            ["-i", "my input.mkv", "-vf", "scale=1280:-2", "out 1.mp4"]
+        );
+        assert_eq!(
+            split_shell(r#"-metadata title="a \"b\" \x" '' x"#).unwrap(),
+            ["-metadata", r#"title=a "b" \x"#, "", "x"]
+        );
+        assert!(split_shell("  ").unwrap().is_empty());
+        assert!(split_shell("-i 'in.mkv").is_err());
+        assert!(split_shell("-i \"in.mkv").is_err());
+    }
+
+    #[test]
+    fn parses_sizes() {
+        assert_eq!(
+            parse_size_option("--log-max-size", "1048576").unwrap(),
+            1048576
+        );
+        assert_eq!(
+            parse_size_option("--log-max-size", "512K").unwrap(),
+            512 * 1024
+        );
+        assert_eq!(
+            parse_size_option("--log-max-size", "10mb").unwrap(),
+            10 << 20
+        );
+        assert_eq!(
+            parse_size_option("--log-max-size", " 1G ").unwrap(),
+            1 << 30
+        );
+        for invalid in ["", "0", "-1", "1.5M", "10T", "M"] {
+            assert!(
+                parse_size_option("--log-max-size", invalid).is_err(),
+                "{invalid}"
+            );
+        }
     }
 }
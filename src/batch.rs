@@ -0,0 +1,491 @@
+use crate::{
+    Error,
+    args::{self, FfmpegArgs, parse_args, split_ffpb_options, split_shell},
+    config::{self, Config},
+    progress::{
+        DONE_COLOR, ERROR_COLOR, Overall, PB_START, bold, dim, fg, format_time, render_overall,
+        reset,
+    },
+    region::Region,
+    runner::{self, RunContext},
+};
+use std::{
+    fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicUsize, Ordering},
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+/// How often the summary line of parallel jobs is refreshed.
+const TICK: Duration = Duration::from_millis(250);
+
+/// One ffmpeg invocation of a batch.
+struct Job {
+    /// Name shown in the results table, the first output or input.
+    name: String,
+    args: Vec<String>,
+}
+
+enum Outcome {
+    Exited(i32),
+    Failed(Error),
+    /// Not started because the batch was interrupted.
+    Skipped,
+}
+
+struct JobResult {
+    name: String,
+    outcome: Outcome,
+    took: Duration,
+}
+
+/// Run `ffpb batch` with the arguments following the subcommand.
+///
+/// ```text
+/// ffpb batch [-j <n>] <job-file> [ffpb options]
+/// ffpb batch [-j <n>] --template '<ffmpeg arguments>' <inputs...> [ffpb options]
+/// ```
+pub fn run_batch(args: &[String]) -> Result<i32, Error> {
+    let mut template = None;
+    let config = Config::load(config::selected_profile(args).as_deref())?;
+    let mut parallel = config.value("--jobs").map(parse_parallel).transpose()?;
+    let mut remaining = Vec::with_capacity(args.len());
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        if arg == "--template" {
+            let value = iter
+                .next()
+                .ok_or_else(|| Error::InvalidOption("--template requires a value".into()))?;
+            template = Some(value.clone());
+        } else if let Some(value) = arg.strip_prefix("--template=") {
+            template = Some(value.to_string());
+        } else if arg == "-j" || arg == "--jobs" {
+            let value = iter
+                .next()
+                .ok_or_else(|| Error::InvalidOption(format!("{arg} requires a value")))?;
+            parallel = Some(parse_parallel(value)?);
+        } else if let Some(value) = arg.strip_prefix("--jobs=") {
+            parallel = Some(parse_parallel(value)?);
+        } else {
+            remaining.push(arg.clone());
+        }
+    }
+
+    // Everything else starting with `--` is an ffpb option applied to every job.
+    let (options, rest) = split_ffpb_options(&remaining);
+
+    let jobs = match template {
+        Some(template) => template_jobs(&template, &rest)?,
+        None => match rest.as_slice() {
+            [file] => file_jobs(Path::new(file))?,
+            _ => {
+                return Err(Error::InvalidOption(
+                    "batch expects a job file or --template with inputs".into(),
+                ));
+            }
+        },
+    };
+
+    if jobs.is_empty() {
+        return Err(Error::InvalidOption("batch has no jobs to run".into()));
+    }
+
+    let started_at = Instant::now();
+    let results = match parallel {
+        Some(workers) if workers > 1 => run_parallel(&jobs, &options, workers, started_at),
+        _ => run_sequential(&jobs, &options, started_at),
+    };
+
+    print_results(&results, started_at.elapsed());
+
+    let all_ok = results
+        .iter()
+        .all(|r| matches!(r.outcome, Outcome::Exited(0)));
+    Ok(if runner::interrupted() {
+        130
+    } else if all_ok {
+        0
+    } else {
+        1
+    })
+}
+
+fn parse_parallel(value: &str) -> Result<usize, Error> {
+    match value.parse::<usize>() {
+        Ok(n) if n > 0 => Ok(n),
+        _ => Err(Error::InvalidOption(format!(
+            "invalid number of parallel jobs '{value}'"
+        ))),
+    }
+}
+
+/// The job's command line, run by `ffmpeg`, or by the `--ffmpeg` binary if
+/// the job's options could be parsed.
+fn job_header(index: usize, jobs: usize, job: &Job, args: &Result<FfmpegArgs, Error>) -> String {
+    let ffmpeg = args
+        .as_ref()
+        .map_or(args::DEFAULT_FFMPEG, |args| args.ffmpeg.as_str());
+    format!(
+        "\x1b[1m[{}/{}]\x1b[0m {}",
+        index + 1,
+        jobs,
+        args::shell_join(ffmpeg, &job.args)
+    )
+}
+
+/// Run the jobs one after another, each with the batch progress below its bar.
+fn run_sequential(jobs: &[Job], options: &[String], started_at: Instant) -> Vec<JobResult> {
+    let mut results = Vec::with_capacity(jobs.len());
+
+    for (index, job) in jobs.iter().enumerate() {
+        if runner::interrupted() {
+            results.push(JobResult {
+                name: job.name.clone(),
+                outcome: Outcome::Skipped,
+                took: Duration::ZERO,
+            });
+            continue;
+        }
+
+        let job_args = options.iter().chain(&job.args).cloned().collect::<Vec<_>>();
+        let parsed = parse_args(&job_args);
+        eprintln!("{}", job_header(index, jobs.len(), job, &parsed));
+
+        let job_started = Instant::now();
+        let ctx = RunContext {
+            overall: Some(Overall {
+                job: index,
+                jobs: jobs.len(),
+                started_at,
+            }),
+            ..RunContext::default()
+        };
+        let outcome = parsed.and_then(|args| runner::run_ffmpeg(&args, &ctx));
+
+        results.push(JobResult {
+            name: job.name.clone(),
+            outcome: match outcome {
+                Ok(code) => Outcome::Exited(code),
+                Err(e) => Outcome::Failed(e),
+            },
+            took: job_started.elapsed(),
+        });
+    }
+    results
+}
+
+/// Run up to `workers` jobs at once, each with its own bar, stacked above a
+/// summary line for the whole batch.
+///
+/// Jobs can't share the terminal's stdin, so keyboard controls are disabled
+/// and ffmpeg runs with `-nostdin`.
+fn run_parallel(
+    jobs: &[Job],
+    options: &[String],
+    workers: usize,
+    started_at: Instant,
+) -> Vec<JobResult> {
+    let region = Region::default();
+    let summary = region.add_bottom_slot();
+    let fractions = Arc::new(Mutex::new(vec![0.0; jobs.len()]));
+    let results = Mutex::new((0..jobs.len()).map(|_| None).collect::<Vec<_>>());
+    let next = AtomicUsize::new(0);
+    let started = AtomicUsize::new(0);
+    let done = AtomicUsize::new(0);
+
+    thread::scope(|scope| {
+        for _ in 0..workers.min(jobs.len()) {
+            scope.spawn(|| {
+                loop {
+                    let index = next.fetch_add(1, Ordering::SeqCst);
+                    let Some(job) = jobs.get(index) else { break };
+
+                    let job_started = Instant::now();
+                    let outcome = if runner::interrupted() {
+                        Outcome::Skipped
+                    } else {
+                        started.fetch_add(1, Ordering::SeqCst);
+                        let job_args = options.iter().chain(&job.args).cloned().collect::<Vec<_>>();
+                        let parsed = parse_args(&job_args);
+                        region.print_above(&job_header(index, jobs.len(), job, &parsed));
+
+                        let fractions = Arc::clone(&fractions);
+                        let ctx = RunContext {
+                            label: Some(job.name.clone()),
+                            region: Some(region.clone()),
+                            on_progress: Some(Arc::new(move |_, fraction| {
+                                if let Some(fraction) = fraction
+                                    && let Ok(mut fractions) = fractions.lock()
+                                {
+                                    fractions[index] = fraction;
+                                }
+                            })),
+                            ..RunContext::default()
+                        };
+                        let outcome = parsed.and_then(|mut args| {
+                            args.no_keys = true;
+                            args.args.insert(0, "-nostdin".to_string());
+                            runner::run_ffmpeg(&args, &ctx)
+                        });
+                        match outcome {
+                            Ok(code) => Outcome::Exited(code),
+                            Err(e) => Outcome::Failed(e),
+                        }
+                    };
+
+                    if let Ok(mut fractions) = fractions.lock() {
+                        fractions[index] = 1.0;
+                    }
+                    if let Ok(mut results) = results.lock() {
+                        results[index] = Some(JobResult {
+                            name: job.name.clone(),
+                            outcome,
+                            took: job_started.elapsed(),
+                        });
+                    }
+                    done.fetch_add(1, Ordering::SeqCst);
+                }
+            });
+        }
+
+        loop {
+            let finished = done.load(Ordering::SeqCst);
+            if finished == jobs.len() {
+                break;
+            }
+            let fraction = fractions
+                .lock()
+                .map(|f| f.iter().sum::<f64>() / jobs.len() as f64)
+                .unwrap_or(0.0);
+            let running = started.load(Ordering::SeqCst).saturating_sub(finished);
+
+            let mut line = String::new();
+            render_overall(
+                &mut line,
+                &format!("{finished}/{} done", jobs.len()),
+                fraction,
+                started_at,
+            );
+            dim(&mut line);
+            line.push_str(&format!(" • {running} running"));
+            reset(&mut line);
+            region.set(summary, vec![line]);
+
+            thread::sleep(TICK);
+        }
+        region.remove(summary);
+    });
+
+    results
+        .into_inner()
+        .unwrap_or_else(|e| e.into_inner())
+        .into_iter()
+        .zip(jobs)
+        .map(|(result, job)| {
+            result.unwrap_or_else(|| JobResult {
+                name: job.name.clone(),
+                outcome: Outcome::Skipped,
+                took: Duration::ZERO,
+            })
+        })
+        .collect()
+}
+
+/// One job per line, `#` starts a comment.
+fn file_jobs(path: &Path) -> Result<Vec<Job>, Error> {
+    let content = fs::read_to_string(path).map_err(|e| {
+        Error::InvalidOption(format!("failed to read job file {} ({e})", path.display()))
+    })?;
+
+    let mut jobs = Vec::new();
+    for line in content.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let mut args = split_shell(line)?;
+        // Allow lines copied from a shell which start with the program name.
+        if matches!(args.first().map(String::as_str), Some("ffmpeg" | "ffpb")) {
+            args.remove(0);
+        }
+        jobs.push(Job {
+            name: job_name(&args),
+            args,
+        });
+    }
+    Ok(jobs)
+}
+
+/// One job per input, with `{input}`, `{stem}` and `{dir}` substituted.
+fn template_jobs(template: &str, inputs: &[String]) -> Result<Vec<Job>, Error> {
+    let words = split_shell(template)?;
+    let mut jobs = Vec::new();
+
+    for input in inputs.iter().flat_map(|pattern| expand_glob(pattern)) {
+        let path = Path::new(&input);
+        let stem = path
+            .file_stem()
+            .map(|s| s.to_string_lossy().to_string())
+            .unwrap_or_default();
+        let dir = path
+            .parent()
+            .map(|p| p.to_string_lossy().to_string())
+            .filter(|p| !p.is_empty())
+            .unwrap_or_else(|| ".".to_string());
+
+        let args = words
+            .iter()
+            .map(|w| {
+                w.replace("{input}", &input)
+                    .replace("{stem}", &stem)
+                    .replace("{dir}", &dir)
+            })
+            .collect::<Vec<_>>();
+        jobs.push(Job {
+            name: job_name(&args),
+            args,
+        });
+    }
+    Ok(jobs)
+}
+
+/// The first output of a job, or its first input.
+fn job_name(args: &[String]) -> String {
+    // A job line may carry ffpb options of its own.
+    let (_, ffmpeg_args) = split_ffpb_options(args);
+    args::output_positions(&ffmpeg_args)
+        .first()
+        .map(|&i| &ffmpeg_args[i])
+        .or_else(|| args.windows(2).find(|w| w[0] == "-i").map(|w| &w[1]))
+        .cloned()
+        .unwrap_or_else(|| args.join(" "))
+}
+
+/// Expand `*` and `?` in the file name part of a pattern. Patterns without
+/// wildcards, or which match nothing, are returned as they are.
+fn expand_glob(pattern: &str) -> Vec<String> {
+    if !pattern.contains(['*', '?']) {
+        return vec![pattern.to_string()];
+    }
+
+    let path = Path::new(pattern);
+    let dir = match path.parent() {
+        Some(p) if !p.as_os_str().is_empty() => p.to_path_buf(),
+        _ => PathBuf::from("."),
+    };
+    let Some(file_pattern) = path.file_name().and_then(|n| n.to_str()) else {
+        return vec![pattern.to_string()];
+    };
+
+    let mut matches = fs::read_dir(&dir)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter(|entry| entry.file_type().is_ok_and(|t| t.is_file()))
+        .filter_map(|entry| {
+            let name = entry.file_name().to_string_lossy().to_string();
+            wildcard_match(file_pattern, &name).then(|| {
+                if path.parent().is_some_and(|p| !p.as_os_str().is_empty()) {
+                    dir.join(&name).to_string_lossy().to_string()
+                } else {
+                    name
+                }
+            })
+        })
+        .collect::<Vec<_>>();
+
+    if matches.is_empty() {
+        return vec![pattern.to_string()];
+    }
+    matches.sort();
+    matches
+}
+
+fn wildcard_match(pattern: &str, name: &str) -> bool {
+    let pattern = pattern.chars().collect::<Vec<_>>();
+    let name = name.chars().collect::<Vec<_>>();
+    let (mut p, mut n) = (0, 0);
+    let mut star = None;
+
+    while n < name.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == name[n]) {
+            p += 1;
+            n += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            star = Some((p, n));
+            p += 1;
+        } else if let Some((sp, sn)) = star {
+            p = sp + 1;
+            n = sn + 1;
+            star = Some((sp, sn + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+fn print_results(results: &[JobResult], took: Duration) {
+    let failed = results
+        .iter()
+        .filter(|r| !matches!(r.outcome, Outcome::Exited(0)))
+        .count();
+
+    let mut buf = String::new();
+    buf.push('\n');
+    bold(&mut buf);
+    buf.push_str("Batch results");
+    reset(&mut buf);
+    dim(&mut buf);
+    buf.push_str(&format!(
+        " • {} jobs • {} failed • {}\n",
+        results.len(),
+        failed,
+        format_time(took.as_micros() as u64)
+    ));
+    reset(&mut buf);
+
+    let width = results.len().to_string().len();
+    for (index, result) in results.iter().enumerate() {
+        let status = match &result.outcome {
+            Outcome::Exited(0) => "ok".to_string(),
+            Outcome::Exited(code) => format!("exit {code}"),
+            Outcome::Failed(e) => e.to_string(),
+            Outcome::Skipped => "skipped".to_string(),
+        };
+
+        buf.push_str(&format!("  {:>width$}  ", index + 1));
+        match result.outcome {
+            Outcome::Exited(0) => fg(&mut buf, DONE_COLOR.0, DONE_COLOR.1, DONE_COLOR.2),
+            Outcome::Skipped => dim(&mut buf),
+            _ => fg(&mut buf, ERROR_COLOR.0, ERROR_COLOR.1, ERROR_COLOR.2),
+        }
+        buf.push_str(&format!(
+            "{:<8}",
+            if status.len() > 8 { "failed" } else { &status }
+        ));
+        reset(&mut buf);
+        buf.push_str(&format!(
+            " {:>8}  ",
+            format_time(result.took.as_micros() as u64)
+        ));
+        fg(&mut buf, PB_START.0, PB_START.1, PB_START.2);
+        buf.push_str(&result.name);
+        reset(&mut buf);
+        if status.len() > 8 {
+            dim(&mut buf);
+            buf.push_str(&format!(" ({status})"));
+            reset(&mut buf);
+        }
+        buf.push('\n');
+    }
+
+    let mut stderr = io::stderr().lock();
+    let _ = write!(stderr, "{buf}");
+    let _ = stderr.flush();
+}
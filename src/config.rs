@@ -0,0 +1,866 @@
+use crate::{
+    Error,
+    args::{SWITCH_OPTIONS, VALUE_OPTIONS, env_var_name, parse_switch},
+    json,
+};
+use std::{
+    collections::BTreeMap,
+    env, fs,
+    path::{Path, PathBuf},
+};
+
+/// Name of the project configuration, looked up in the current directory
+/// and its parents.
+const PROJECT_FILE: &str = ".ffpb.toml";
+
+/// Table of named argument lists, expanded by `--preset`.
+const PRESETS_TABLE: &str = "presets";
+
+/// Options of `ffpb batch` which can be configured as well.
+const BATCH_OPTIONS: &[&str] = &["--jobs"];
+
+/// A value from a configuration file.
+#[derive(Clone)]
+pub enum Value {
+    Str(String),
+    /// Kept as written, e.g. `10` or `1.5`.
+    Num(String),
+    Bool(bool),
+    Array(Vec<Value>),
+}
+
+/// One `key = value` line of a configuration file.
+pub struct Entry {
+    /// Table the key is in, e.g. `profile.fast`, empty at the top.
+    pub table: String,
+    pub key: String,
+    pub value: Value,
+    pub line: usize,
+}
+
+/// A parsed configuration file.
+pub struct Document {
+    /// Every table header with its line, e.g. `profile.fast`.
+    pub tables: Vec<(String, usize)>,
+    pub entries: Vec<Entry>,
+}
+
+/// Where an effective setting comes from.
+#[derive(Clone)]
+enum Source {
+    File {
+        path: PathBuf,
+        profile: Option<String>,
+    },
+    Env(String),
+    CommandLine,
+}
+
+#[derive(Clone)]
+enum Setting {
+    Switch(bool),
+    Text(String),
+}
+
+/// ffpb options from the configuration files and `FFPB_*` environment
+/// variables. From lowest to highest precedence: the user configuration, the
+/// project configuration, the environment, then the command line.
+///
+/// Each file may have `[profile.<name>]` tables, whose settings apply on top
+/// of the file's own when the profile is selected with `--profile`.
+#[derive(Default)]
+pub struct Config {
+    user_file: Option<PathBuf>,
+    project_file: Option<PathBuf>,
+    profile: Option<String>,
+    settings: BTreeMap<&'static str, (Setting, Source)>,
+    /// Presets by name, with the file defining them.
+    presets: BTreeMap<String, (Vec<String>, PathBuf)>,
+}
+
+impl Config {
+    pub fn load(profile: Option<&str>) -> Result<Self, Error> {
+        let mut config = Self {
+            user_file: user_config_path().filter(|path| path.is_file()),
+            project_file: project_config_path(),
+            profile: profile.map(String::from),
+            settings: BTreeMap::new(),
+            presets: BTreeMap::new(),
+        };
+
+        let mut profile_found = false;
+        for path in [config.user_file.clone(), config.project_file.clone()]
+            .into_iter()
+            .flatten()
+        {
+            profile_found |= config.apply_file(&path)?;
+        }
+        if let Some(profile) = profile
+            && !profile_found
+        {
+            return Err(Error::InvalidOption(format!(
+                "profile {profile} not found, expected a [profile.{profile}] table in {} or {PROJECT_FILE}",
+                user_config_path().map_or_else(
+                    || "the user configuration".to_string(),
+                    |path| path.display().to_string()
+                ),
+            )));
+        }
+
+        config.apply_env(|name| env::var(name).ok())?;
+        Ok(config)
+    }
+
+    /// The effective settings as command line options, to be parsed before
+    /// the real command line.
+    pub fn options(&self) -> Vec<String> {
+        self.settings
+            .iter()
+            .filter(|(option, _)| !BATCH_OPTIONS.contains(option))
+            .filter_map(|(option, (setting, _))| match setting {
+                Setting::Switch(true) => Some(option.to_string()),
+                Setting::Switch(false) => None,
+                Setting::Text(value) => Some(format!("{option}={value}")),
+            })
+            .collect()
+    }
+
+    /// The value configured for an option, e.g. `--jobs`.
+    pub fn value(&self, option: &str) -> Option<&str> {
+        match self.settings.get(option) {
+            Some((Setting::Text(value), _)) => Some(value),
+            _ => None,
+        }
+    }
+
+    /// The arguments of a preset, with the presets it includes through
+    /// `"@<name>"` elements expanded in place.
+    pub fn preset(&self, name: &str) -> Result<Vec<String>, Error> {
+        let mut expanded = Vec::new();
+        self.expand_preset(name, &mut Vec::new(), &mut expanded)?;
+        Ok(expanded)
+    }
+
+    fn expand_preset<'a>(
+        &'a self,
+        name: &'a str,
+        including: &mut Vec<&'a str>,
+        expanded: &mut Vec<String>,
+    ) -> Result<(), Error> {
+        let Some((args, _)) = self.presets.get(name) else {
+            let known = self.presets.keys().cloned().collect::<Vec<_>>();
+            return Err(Error::InvalidOption(if known.is_empty() {
+                format!("unknown preset {name}, there is no [{PRESETS_TABLE}] table")
+            } else {
+                format!("unknown preset {name} (expected {})", known.join(", "))
+            }));
+        };
+        if including.contains(&name) {
+            including.push(name);
+            return Err(Error::InvalidOption(format!(
+                "preset {name} includes itself ({})",
+                including.join(" -> ")
+            )));
+        }
+
+        including.push(name);
+        for arg in args {
+            match arg.strip_prefix('@') {
+                Some(included) => self.expand_preset(included, including, expanded)?,
+                None => expanded.push(arg.clone()),
+            }
+        }
+        including.pop();
+        Ok(())
+    }
+
+    /// Apply ffpb options given on the command line, as split off by
+    /// `split_ffpb_options`.
+    pub fn apply_args(&mut self, options: &[String]) -> Result<(), Error> {
+        let mut iter = options.iter();
+        while let Some(arg) = iter.next() {
+            let (name, inline) = match arg.split_once('=') {
+                Some((name, value)) => (name, Some(value.to_string())),
+                None => (arg.as_str(), None),
+            };
+            let Some(option) = known_option(name) else {
+                continue;
+            };
+            let setting = if SWITCH_OPTIONS.contains(&option) {
+                Setting::Switch(parse_switch(option, inline.as_deref())?)
+            } else {
+                match inline.or_else(|| iter.next().cloned()) {
+                    Some(value) => Setting::Text(value),
+                    None => continue,
+                }
+            };
+            self.settings.insert(option, (setting, Source::CommandLine));
+        }
+        Ok(())
+    }
+
+    /// The effective settings in the configuration file format, each with
+    /// where it comes from.
+    pub fn render(&self) -> String {
+        let describe = |path: &Option<PathBuf>, fallback: Option<PathBuf>| match path {
+            Some(path) => path.display().to_string(),
+            None => match fallback {
+                Some(path) => format!("{} (not found)", path.display()),
+                None => "not found".to_string(),
+            },
+        };
+
+        let mut out = String::new();
+        out.push_str(&format!(
+            "# user config: {}\n",
+            describe(&self.user_file, user_config_path())
+        ));
+        out.push_str(&format!(
+            "# project config: {}\n",
+            describe(&self.project_file, None)
+        ));
+        if let Some(ref profile) = self.profile {
+            out.push_str(&format!("# profile: {profile}\n"));
+        }
+
+        for (option, (setting, source)) in &self.settings {
+            let value = match setting {
+                Setting::Switch(on) => on.to_string(),
+                Setting::Text(value)
+                    if !value.is_empty()
+                        && value.chars().all(|c| c.is_ascii_digit() || c == '.') =>
+                {
+                    value.clone()
+                }
+                Setting::Text(value) => json::string(value),
+            };
+            let source = match source {
+                Source::File {
+                    path,
+                    profile: Some(profile),
+                } => format!("{} [profile.{profile}]", path.display()),
+                Source::File {
+                    path,
+                    profile: None,
+                } => path.display().to_string(),
+                Source::Env(var) => var.clone(),
+                Source::CommandLine => "command line".to_string(),
+            };
+            out.push_str(&format!(
+                "{} = {value}  # {source}\n",
+                option.trim_start_matches('-')
+            ));
+        }
+
+        if !self.presets.is_empty() {
+            out.push_str(&format!("\n[{PRESETS_TABLE}]\n"));
+        }
+        for (name, (args, path)) in &self.presets {
+            out.push_str(&format!(
+                "{} = [{}]  # {}\n",
+                key(name),
+                args.iter()
+                    .map(|arg| json::string(arg))
+                    .collect::<Vec<_>>()
+                    .join(", "),
+                path.display()
+            ));
+        }
+        out
+    }
+
+    /// Apply the settings of a file, then those of the selected profile.
+    /// Returns whether the file has the profile.
+    fn apply_file(&mut self, path: &Path) -> Result<bool, Error> {
+        let document = read_file(path)?;
+        let profile_table = self.profile.as_ref().map(|p| format!("profile.{p}"));
+        let invalid = |line: usize, message: String| {
+            Error::InvalidOption(format!("{}:{line}: {message}", path.display()))
+        };
+
+        for (table, line) in &document.tables {
+            match table.strip_prefix("profile.") {
+                Some("") => return Err(invalid(*line, "profile without a name".into())),
+                Some(_) => {}
+                None if table == PRESETS_TABLE => {}
+                None => return Err(invalid(*line, format!("unknown table [{table}]"))),
+            }
+        }
+        let profile_found = document
+            .tables
+            .iter()
+            .any(|(table, _)| profile_table.as_ref() == Some(table));
+
+        // Every entry is checked, including those of other profiles, while
+        // the base settings are applied before the selected profile's.
+        let mut base = Vec::new();
+        let mut profile = Vec::new();
+        for entry in &document.entries {
+            if entry.table == PRESETS_TABLE {
+                let args = match entry.value {
+                    Value::Array(ref items) => items
+                        .iter()
+                        .map(|item| match item {
+                            Value::Str(arg) => Some(arg.clone()),
+                            _ => None,
+                        })
+                        .collect::<Option<Vec<_>>>(),
+                    _ => None,
+                }
+                .ok_or_else(|| {
+                    invalid(
+                        entry.line,
+                        format!("preset {} expects an array of strings", entry.key),
+                    )
+                })?;
+                self.presets
+                    .insert(entry.key.clone(), (args, path.to_path_buf()));
+                continue;
+            }
+
+            let option = known_option(&format!("--{}", entry.key.replace('_', "-")))
+                .ok_or_else(|| invalid(entry.line, format!("unknown option {}", entry.key)))?;
+            let setting = match (&entry.value, SWITCH_OPTIONS.contains(&option)) {
+                (Value::Bool(on), true) => Setting::Switch(*on),
+                (Value::Str(value) | Value::Num(value), false) => Setting::Text(value.clone()),
+                (_, true) => {
+                    return Err(invalid(
+                        entry.line,
+                        format!("{} expects true or false", entry.key),
+                    ));
+                }
+                (_, false) => {
+                    return Err(invalid(
+                        entry.line,
+                        format!("{} expects a string or a number", entry.key),
+                    ));
+                }
+            };
+            if entry.table.is_empty() {
+                base.push((option, setting));
+            } else if profile_table.as_ref() == Some(&entry.table) {
+                profile.push((option, setting));
+            }
+        }
+
+        for (settings, profile) in [(base, None), (profile, self.profile.clone())] {
+            for (option, setting) in settings {
+                let source = Source::File {
+                    path: path.to_path_buf(),
+                    profile: profile.clone(),
+                };
+                self.settings.insert(option, (setting, source));
+            }
+        }
+        Ok(profile_found)
+    }
+
+    /// `FFPB_*` environment variables, e.g. `FFPB_CLEAN=1` or `FFPB_COLOR=never`,
+    /// as returned by `var`.
+    pub fn apply_env(&mut self, var: impl Fn(&str) -> Option<String>) -> Result<(), Error> {
+        for &option in SWITCH_OPTIONS
+            .iter()
+            .chain(VALUE_OPTIONS)
+            .chain(BATCH_OPTIONS)
+        {
+            let Some(option) = known_option(option) else {
+                continue;
+            };
+            let name = env_var_name(option);
+            let Some(value) = var(&name) else { continue };
+            let setting = if SWITCH_OPTIONS.contains(&option) {
+                Setting::Switch(!value.is_empty() && parse_switch(&name, Some(&value))?)
+            } else if value.is_empty() {
+                continue;
+            } else {
+                Setting::Text(value)
+            };
+            self.settings.insert(option, (setting, Source::Env(name)));
+        }
+        Ok(())
+    }
+}
+
+/// The profile selected with `--profile` on the command line, or `FFPB_PROFILE`.
+pub fn selected_profile(args: &[String]) -> Option<String> {
+    let mut profile = None;
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        if arg == "--profile" {
+            profile = iter.next().cloned();
+        } else if let Some(value) = arg.strip_prefix("--profile=") {
+            profile = Some(value.to_string());
+        }
+    }
+    profile
+        .or_else(|| env::var(env_var_name("--profile")).ok())
+        .filter(|profile| !profile.is_empty())
+}
+
+/// A key as written in a configuration file, quoted unless it is bare.
+fn key(name: &str) -> String {
+    if !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        name.to_string()
+    } else {
+        json::string(name)
+    }
+}
+
+/// The option named `name` if it can be configured, e.g. `--log-file`.
+fn known_option(name: &str) -> Option<&'static str> {
+    SWITCH_OPTIONS
+        .iter()
+        .chain(VALUE_OPTIONS)
+        .chain(BATCH_OPTIONS)
+        .copied()
+        .filter(|&option| option != "--profile" && option != "--preset")
+        .find(|&option| option == name)
+}
+
+/// `$XDG_CONFIG_HOME/ffpb/config.toml`, falling back to `~/.config` (or
+/// `%APPDATA%` on Windows).
+fn user_config_path() -> Option<PathBuf> {
+    let dir = match env::var_os("XDG_CONFIG_HOME").filter(|dir| !dir.is_empty()) {
+        Some(dir) => PathBuf::from(dir),
+        None if cfg!(windows) => PathBuf::from(env::var_os("APPDATA")?),
+        None => PathBuf::from(env::var_os("HOME")?).join(".config"),
+    };
+    Some(dir.join("ffpb").join("config.toml"))
+}
+
+/// The nearest `.ffpb.toml` in the current directory or its parents.
+fn project_config_path() -> Option<PathBuf> {
+    env::current_dir()
+        .ok()?
+        .ancestors()
+        .map(|dir| dir.join(PROJECT_FILE))
+        .find(|path| path.is_file())
+}
+
+pub fn read_file(path: &Path) -> Result<Document, Error> {
+    let text = fs::read_to_string(path)
+        .map_err(|e| Error::InvalidOption(format!("failed to read {} ({e})", path.display())))?;
+    parse(&text).map_err(|(line, message)| {
+        Error::InvalidOption(format!("{}:{line}: {message}", path.display()))
+    })
+}
+
+/// Parse the subset of TOML ffpb's configuration uses: tables, bare or
+/// quoted keys, strings, numbers, booleans and arrays. Errors carry the
+/// line number.
+pub fn parse(text: &str) -> Result<Document, (usize, String)> {
+    let mut parser = Parser {
+        chars: text.chars().collect(),
+        pos: 0,
+        line: 1,
+    };
+    let mut tables = Vec::new();
+    let mut entries = Vec::new();
+    let mut table = String::new();
+
+    loop {
+        parser.skip_blank(true);
+        let Some(c) = parser.peek() else { break };
+        let line = parser.line;
+
+        if c == '[' {
+            parser.pos += 1;
+            let name = parser.take_while(|c| c != ']' && c != '\n');
+            if !parser.eat(']') {
+                return Err((line, "unclosed table header".into()));
+            }
+            table = name.trim().to_string();
+            if table.is_empty() {
+                return Err((line, "empty table name".into()));
+            }
+            tables.push((table.clone(), line));
+        } else {
+            let key = parser.key()?;
+            parser.skip_blank(false);
+            if !parser.eat('=') {
+                return Err((line, format!("expected = after {key}")));
+            }
+            parser.skip_blank(false);
+            let value = parser.value()?;
+            entries.push(Entry {
+                table: table.clone(),
+                key,
+                value,
+                line,
+            });
+        }
+
+        parser.skip_blank(false);
+        match parser.peek() {
+            None | Some('\n') => {}
+            Some(c) => return Err((parser.line, format!("unexpected {c:?}"))),
+        }
+    }
+    Ok(Document { tables, entries })
+}
+
+struct Parser {
+    chars: Vec<char>,
+    pos: usize,
+    line: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.pos += 1;
+        if c == '\n' {
+            self.line += 1;
+        }
+        Some(c)
+    }
+
+    fn eat(&mut self, expected: char) -> bool {
+        if self.peek() == Some(expected) {
+            self.next();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn take_while(&mut self, f: impl Fn(char) -> bool) -> String {
+        let mut taken = String::new();
+        while let Some(c) = self.peek().filter(|c| f(*c)) {
+            taken.push(c);
+            self.next();
+        }
+        taken
+    }
+
+    /// Skip spaces and comments, and line breaks too if `newlines`.
+    fn skip_blank(&mut self, newlines: bool) {
+        while let Some(c) = self.peek() {
+            match c {
+                ' ' | '\t' | '\r' => {
+                    self.next();
+                }
+                '\n' if newlines => {
+                    self.next();
+                }
+                '#' => {
+                    self.take_while(|c| c != '\n');
+                }
+                _ => break,
+            }
+        }
+    }
+
+    fn key(&mut self) -> Result<String, (usize, String)> {
+        match self.peek() {
+            Some('"') => self.basic_string(),
+            Some('\'') => self.literal_string(),
+            _ => {
+                let key = self.take_while(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+                if key.is_empty() {
+                    Err((self.line, "expected a key".into()))
+                } else {
+                    Ok(key)
+                }
+            }
+        }
+    }
+
+    fn value(&mut self) -> Result<Value, (usize, String)> {
+        match self.peek() {
+            Some('"') => self.basic_string().map(Value::Str),
+            Some('\'') => self.literal_string().map(Value::Str),
+            Some('[') => {
+                self.next();
+                let mut items = Vec::new();
+                loop {
+                    self.skip_blank(true);
+                    if self.eat(']') {
+                        break;
+                    }
+                    items.push(self.value()?);
+                    self.skip_blank(true);
+                    if self.eat(']') {
+                        break;
+                    }
+                    if !self.eat(',') {
+                        return Err((self.line, "expected , or ] in array".into()));
+                    }
+                }
+                Ok(Value::Array(items))
+            }
+            _ => {
+                let word = self.take_while(|c| {
+                    c.is_ascii_alphanumeric() || matches!(c, '+' | '-' | '.' | '_')
+                });
+                match word.as_str() {
+                    "true" => Ok(Value::Bool(true)),
+                    "false" => Ok(Value::Bool(false)),
+                    _ if !word.is_empty() && word.replace('_', "").parse::<f64>().is_ok() => {
+                        Ok(Value::Num(word.replace('_', "")))
+                    }
+                    "" => Err((self.line, "expected a value".into())),
+                    _ => Err((
+                        self.line,
+                        format!("invalid value {word}, strings need quotes"),
+                    )),
+                }
+            }
+        }
+    }
+
+    fn basic_string(&mut self) -> Result<String, (usize, String)> {
+        let line = self.line;
+        self.next();
+        let mut value = String::new();
+        loop {
+            match self.next() {
+                Some('"') => return Ok(value),
+                Some('\\') => match self.next() {
+                    Some('n') => value.push('\n'),
+                    Some('t') => value.push('\t'),
+                    Some('r') => value.push('\r'),
+                    Some('"') => value.push('"'),
+                    Some('\\') => value.push('\\'),
+                    Some('u') => {
+                        let hex = (0..4).filter_map(|_| self.next()).collect::<String>();
+                        let c = u32::from_str_radix(&hex, 16)
+                            .ok()
+                            .and_then(char::from_u32)
+                            .ok_or((self.line, format!("invalid escape \\u{hex}")))?;
+                        value.push(c);
+                    }
+                    Some(c) => return Err((self.line, format!("invalid escape \\{c}"))),
+                    None => break,
+                },
+                Some('\n') | None => break,
+                Some(c) => value.push(c),
+            }
+        }
+        Err((line, "unclosed string".into()))
+    }
+
+    fn literal_string(&mut self) -> Result<String, (usize, String)> {
+        let line = self.line;
+        self.next();
+        let value = self.take_while(|c| c != '\'' && c != '\n');
+        if self.eat('\'') {
+            Ok(value)
+        } else {
+            Err((line, "unclosed string".into()))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(profile: Option<&str>) -> Config {
+        Config {
+            profile: profile.map(String::from),
+            ..Config::default()
+        }
+    }
+
+    /// Write `text` to a file of its own in the temporary directory.
+    fn file(name: &str, text: &str) -> PathBuf {
+        let path = env::temp_dir().join(format!(
+            "ffpb-config-test-{}-{name}.toml",
+            std::process::id()
+        ));
+        fs::write(&path, text).unwrap();
+        path
+    }
+
+    fn error(text: &str) -> (usize, String) {
+        parse(text).err().expect("should not parse")
+    }
+
+    #[test]
+    fn parses_values_keys_and_tables() {
+        let document = parse(
+            "# comment\n\
+             clean = true # trailing comment\n\
+             log_file = 'C:\\logs\\ffpb.log'\n\
+             \"quoted key\" = \"tab\\there \\u00e9 \\\"q\\\"\"\n\
+             retries = 3\n\
+             \n\
+             [profile.fast]\n\
+             speed = -1.5\n\
+             args = [\n  \"-c:v\", # codec\n  'libx264',\n]\n\
+             empty = []\n",
+        )
+        .unwrap();
+
+        let tables = document
+            .tables
+            .iter()
+            .map(|(t, l)| (t.as_str(), *l))
+            .collect::<Vec<_>>();
+        assert_eq!(tables, [("profile.fast", 7)]);
+
+        let entries = document
+            .entries
+            .iter()
+            .map(|e| (e.table.as_str(), e.key.as_str(), e.line))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            entries,
+            [
+                ("", "clean", 2),
+                ("", "log_file", 3),
+                ("", "quoted key", 4),
+                ("", "retries", 5),
+                ("profile.fast", "speed", 8),
+                ("profile.fast", "args", 9),
+                ("profile.fast", "empty", 13),
+            ]
+        );
+
+        let values = &document.entries;
+        assert!(matches!(values[0].value, Value::Bool(true)));
+        assert!(matches!(values[1].value, Value::Str(ref s) if s == "C:\\logs\\ffpb.log"));
+        assert!(matches!(values[2].value, Value::Str(ref s) if s == "tab\there é \"q\""));
+        assert!(matches!(values[3].value, Value::Num(ref n) if n == "3"));
+        assert!(matches!(values[4].value, Value::Num(ref n) if n == "-1.5"));
+        match values[5].value {
+            Value::Array(ref items) => {
+                let items = items
+                    .iter()
+                    .map(|item| match item {
+                        Value::Str(s) => s.as_str(),
+                        _ => panic!("expected strings"),
+                    })
+                    .collect::<Vec<_>>();
+                assert_eq!(items, ["-c:v", "libx264"]);
+            }
+            _ => panic!("expected an array"),
+        }
+        assert!(matches!(values[6].value, Value::Array(ref items) if items.is_empty()));
+    }
+
+    #[test]
+    fn reports_errors_with_line_numbers() {
+        assert_eq!(
+            error("a = 1\nb = \"open\nc = 2"),
+            (2, "unclosed string".into())
+        );
+        assert_eq!(error("\n\na = 'open"), (3, "unclosed string".into()));
+        assert_eq!(
+            error("a = 1\n[profile.x\n"),
+            (2, "unclosed table header".into())
+        );
+        assert_eq!(error("[ ]"), (1, "empty table name".into()));
+        assert_eq!(error("a = 1\nb\n"), (2, "expected = after b".into()));
+        assert_eq!(
+            error("a = never"),
+            (1, "invalid value never, strings need quotes".into())
+        );
+        assert_eq!(
+            error("a = \"\\uZZZZ\""),
+            (1, "invalid escape \\uZZZZ".into())
+        );
+        assert_eq!(error("a = \"\\q\""), (1, "invalid escape \\q".into()));
+        assert_eq!(
+            error("a = [1,\n2\n3]"),
+            (3, "expected , or ] in array".into())
+        );
+        assert_eq!(error("a = 1 b = 2"), (1, "unexpected 'b'".into()));
+    }
+
+    #[test]
+    fn profile_overrides_base_settings() {
+        let path = file(
+            "profile",
+            "clean = true\nretries = 1\n[profile.fast]\nclean = false\nretries = 3\n[profile.other]\ncolor = \"never\"\n",
+        );
+        let mut fast = config(Some("fast"));
+        assert!(fast.apply_file(&path).unwrap());
+        let mut base = config(None);
+        assert!(!base.apply_file(&path).unwrap());
+        let mut missing = config(Some("missing"));
+        assert!(!missing.apply_file(&path).unwrap());
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(fast.options(), ["--retries=3"]);
+        assert_eq!(base.options(), ["--clean", "--retries=1"]);
+        assert_eq!(missing.options(), base.options());
+    }
+
+    #[test]
+    fn rejects_unknown_keys_in_any_profile() {
+        let path = file("unknown", "[profile.other]\nbogus = 1\n");
+        let result = config(None).apply_file(&path);
+        fs::remove_file(&path).unwrap();
+        let Err(Error::InvalidOption(message)) = result else {
+            panic!("should fail");
+        };
+        assert!(message.ends_with(":2: unknown option bogus"), "{message}");
+    }
+
+    #[test]
+    fn environment_beats_files() {
+        let path = file("env", "clean = true\ncolor = \"never\"\nverify = true\n");
+        let mut config = config(None);
+        config.apply_file(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        config
+            .apply_env(|name| match name {
+                "FFPB_CLEAN" => Some("0".into()),
+                "FFPB_COLOR" => Some("always".into()),
+                _ => None,
+            })
+            .unwrap();
+        assert_eq!(config.options(), ["--color=always", "--verify"]);
+
+        config
+            .apply_args(&["--clean".into(), "--color".into(), "auto".into()])
+            .unwrap();
+        assert_eq!(config.options(), ["--clean", "--color=auto", "--verify"]);
+
+        config
+            .apply_args(&["--verify=false".into(), "--clean".into()])
+            .unwrap();
+        assert_eq!(config.options(), ["--clean", "--color=auto"]);
+        assert!(config.apply_args(&["--verify=maybe".into()]).is_err());
+
+        let invalid = config.apply_env(|name| (name == "FFPB_CLEAN").then(|| "maybe".into()));
+        assert!(invalid.is_err());
+    }
+
+    #[test]
+    fn hook_variables_are_no_options() {
+        for option in SWITCH_OPTIONS
+            .iter()
+            .chain(VALUE_OPTIONS)
+            .chain(BATCH_OPTIONS)
+        {
+            assert!(!env_var_name(option).starts_with("FFPB_HOOK_"), "{option}");
+        }
+    }
+
+    #[test]
+    fn expands_presets() {
+        let path = file(
+            "presets",
+            "[presets]\naac = [\"-c:a\", \"aac\"]\nweb = [\"-crf\", \"20\", \"@aac\"]\nloop = [\"@loop\"]\n",
+        );
+        let mut config = config(None);
+        config.apply_file(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(config.preset("web").unwrap(), ["-crf", "20", "-c:a", "aac"]);
+        assert!(config.preset("loop").is_err());
+        assert!(config.preset("missing").is_err());
+    }
+}
@@ -0,0 +1,98 @@
+use crate::{
+    Error,
+    args::{self, FfmpegArgs, is_local_file},
+    progress::format_time_clock,
+    runner::{self, compute_effective_duration},
+    transport::Transport,
+    two_pass, verify,
+};
+use std::path::Path;
+
+/// Print what `--dry-run` shows instead of running ffmpeg: every command ffpb
+/// would execute, shell-quoted and including the `-progress` ffpb adds,
+/// followed by what ffpb made of them as shell comments.
+///
+/// Nothing is opened, so the pipe, socket or port of `-progress` is shown as
+/// a placeholder which ffpb fills in at run time.
+pub fn print(args: &FfmpegArgs) -> Result<(), Error> {
+    let commands = if args.two_pass {
+        let passes = two_pass::passes(args)?;
+        vec![passes.first, passes.second]
+    } else {
+        vec![args.clone()]
+    };
+    let progress_url = Transport::for_args(args).placeholder_url();
+    for command in &commands {
+        println!(
+            "{}",
+            args::shell_join(
+                &command.ffmpeg,
+                &runner::command_args(command, progress_url)
+            )
+        );
+    }
+    if progress_url.contains('<') {
+        println!("# -progress: ffpb fills in {progress_url} at run time");
+    }
+
+    let inputs = args
+        .args
+        .windows(2)
+        .filter(|w| w[0] == "-i")
+        .map(|w| w[1].as_str())
+        .collect::<Vec<_>>();
+    println!("# inputs: {}", list(&inputs));
+    println!("# outputs: {}", list(&args.outputs));
+
+    let times = [("-ss", args.ss), ("-to", args.to), ("-t", args.t)]
+        .into_iter()
+        .filter_map(|(name, secs)| Some(format!("{name} {}s", secs?)))
+        .collect::<Vec<_>>();
+    println!("# time options: {}", list(&times));
+    println!(
+        "# expected duration: {}",
+        expected_duration(args, inputs.first().copied())
+    );
+    Ok(())
+}
+
+/// The effective duration ffpb would expect, probing the first input with
+/// ffprobe if the time options don't settle it. ffmpeg itself reports the
+/// duration of the first input as well.
+fn expected_duration(args: &FfmpegArgs, input: Option<&str>) -> String {
+    if let Some(us) = compute_effective_duration(args, None) {
+        return format!("{} (from the time options)", format_time_clock(us));
+    }
+    let Some(input) = input else {
+        return "unknown (no input)".to_string();
+    };
+    if !is_local_file(input) || !Path::new(input).exists() {
+        return format!("unknown until ffmpeg opens {input}");
+    }
+
+    match verify::input_duration(&args.ffprobe(), input) {
+        Ok(total_us) => {
+            match compute_effective_duration(args, Some(total_us as f64 / 1_000_000.0)) {
+                Some(us) => format!(
+                    "{} ({input} is {})",
+                    format_time_clock(us),
+                    format_time_clock(total_us)
+                ),
+                None => format!("none, -ss is past the end of {input}"),
+            }
+        }
+        Err(e) => format!("unknown, ffprobe failed on {input} ({e})"),
+    }
+}
+
+fn list<S: AsRef<str>>(items: &[S]) -> String {
+    if items.is_empty() {
+        "none".to_string()
+    } else {
+        items
+            .iter()
+            .map(AsRef::as_ref)
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+}
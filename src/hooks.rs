@@ -0,0 +1,131 @@
+use crate::{
+    Error,
+    args::FfmpegArgs,
+    progress::{WARN_COLOR, fg, reset},
+    region::Region,
+    runner::{Attempt, print_line},
+};
+use std::{process::Command, time::Duration};
+
+/// Run the `--on-success` or `--on-failure` hook, then `--on-finish`.
+///
+/// Hooks run through the shell with the outcome of the run in `FFPB_HOOK_*`
+/// environment variables, which no option is read from. Their output is
+/// printed once they exit, and a failing hook only produces a warning.
+pub fn run_hooks(
+    args: &FfmpegArgs,
+    result: &Result<Attempt, Error>,
+    elapsed: Duration,
+    region: &Option<Region>,
+) {
+    let succeeded = matches!(result, Ok(attempt) if attempt.code == 0);
+    let hooks = [
+        if succeeded {
+            &args.on_success
+        } else {
+            &args.on_failure
+        },
+        &args.on_finish,
+    ];
+    if hooks.iter().all(|hook| hook.is_none()) {
+        return;
+    }
+
+    let env = hook_env(args, result, succeeded, elapsed);
+    for hook in hooks.into_iter().flatten() {
+        run_hook(hook, &env, region);
+    }
+}
+
+fn hook_env(
+    args: &FfmpegArgs,
+    result: &Result<Attempt, Error>,
+    succeeded: bool,
+    elapsed: Duration,
+) -> Vec<(&'static str, String)> {
+    let inputs = args
+        .args
+        .windows(2)
+        .filter(|w| w[0] == "-i")
+        .map(|w| w[1].clone())
+        .collect::<Vec<_>>();
+
+    let mut env = vec![
+        (
+            "FFPB_HOOK_STATUS",
+            if succeeded { "success" } else { "failure" }.to_string(),
+        ),
+        (
+            "FFPB_HOOK_INPUT",
+            inputs.first().cloned().unwrap_or_default(),
+        ),
+        ("FFPB_HOOK_INPUTS", inputs.join("\n")),
+        (
+            "FFPB_HOOK_OUTPUT",
+            args.outputs.first().cloned().unwrap_or_default(),
+        ),
+        ("FFPB_HOOK_OUTPUTS", args.outputs.join("\n")),
+        ("FFPB_HOOK_ELAPSED", format!("{:.3}", elapsed.as_secs_f64())),
+    ];
+
+    match result {
+        Ok(attempt) => {
+            let duration = attempt.out_time_us as f64 / 1_000_000.0;
+            env.push(("FFPB_HOOK_EXIT_CODE", attempt.code.to_string()));
+            env.push(("FFPB_HOOK_DURATION", format!("{duration:.3}")));
+            env.push(("FFPB_HOOK_SIZE", attempt.total_size.to_string()));
+            env.push((
+                "FFPB_HOOK_SPEED",
+                format!("{:.2}", duration / elapsed.as_secs_f64().max(0.001)),
+            ));
+        }
+        Err(e) => {
+            env.push(("FFPB_HOOK_EXIT_CODE", e.exit_code().to_string()));
+            env.push(("FFPB_HOOK_ERROR", e.to_string()));
+        }
+    }
+
+    if let Some(ref log_file) = args.log_file {
+        env.push(("FFPB_HOOK_LOG_FILE", log_file.to_string_lossy().to_string()));
+    }
+    env
+}
+
+fn run_hook(hook: &str, env: &[(&'static str, String)], region: &Option<Region>) {
+    #[cfg(windows)]
+    let mut command = {
+        let mut command = Command::new("cmd");
+        command.args(["/C", hook]);
+        command
+    };
+    #[cfg(not(windows))]
+    let mut command = {
+        let mut command = Command::new("sh");
+        command.args(["-c", hook]);
+        command
+    };
+
+    let problem = match command.envs(env.iter().cloned()).output() {
+        Ok(output) => {
+            for line in String::from_utf8_lossy(&output.stdout)
+                .lines()
+                .chain(String::from_utf8_lossy(&output.stderr).lines())
+            {
+                print_line(region, line);
+            }
+            (!output.status.success()).then(|| match output.status.code() {
+                Some(code) => format!("exited with status {code}"),
+                None => "was terminated by a signal".to_string(),
+            })
+        }
+        Err(e) => Some(format!("failed to start ({e})")),
+    };
+
+    if let Some(problem) = problem {
+        let mut line = String::new();
+        fg(&mut line, WARN_COLOR.0, WARN_COLOR.1, WARN_COLOR.2);
+        line.push_str(&format!("hook '{hook}' {problem}"));
+        reset(&mut line);
+        print_line(region, &line);
+    }
+}
@@ -0,0 +1,184 @@
+use std::{
+    io::{Read, Write},
+    net::{TcpStream, ToSocketAddrs},
+    time::Duration,
+};
+
+/// Parts of an `http://` url.
+pub struct Url {
+    pub host: String,
+    pub port: u16,
+    pub path: String,
+}
+
+impl Url {
+    /// Only plain `http://` is supported.
+    pub fn parse(url: &str) -> Option<Self> {
+        let rest = url.strip_prefix("http://")?;
+        let (authority, path) = match rest.find('/') {
+            Some(i) => (&rest[..i], &rest[i..]),
+            None => (rest, "/"),
+        };
+        let (host, port) = if let Some(rest) = authority.strip_prefix('[') {
+            // IPv6 literal, e.g. [::1]:8080
+            let (host, after) = rest.split_once(']')?;
+            match after.strip_prefix(':') {
+                Some(port) => (host, port.parse().ok()?),
+                None => (host, 80),
+            }
+        } else {
+            match authority.rsplit_once(':') {
+                Some((host, port)) => (host, port.parse().ok()?),
+                None => (authority, 80),
+            }
+        };
+        if host.is_empty() {
+            return None;
+        }
+
+        Some(Self {
+            host: host.to_string(),
+            port,
+            path: path.to_string(),
+        })
+    }
+
+    /// `host:port` as used in the `Host` header.
+    fn authority(&self) -> String {
+        if self.host.contains(':') {
+            format!("[{}]:{}", self.host, self.port)
+        } else {
+            format!("{}:{}", self.host, self.port)
+        }
+    }
+}
+
+/// POST a JSON body and return the response status code.
+pub fn post_json(url: &Url, body: &str, timeout: Duration) -> Result<u16, String> {
+    let addr = (url.host.as_str(), url.port)
+        .to_socket_addrs()
+        .map_err(|e| e.to_string())?
+        .next()
+        .ok_or_else(|| format!("{} did not resolve", url.host))?;
+
+    let mut stream = TcpStream::connect_timeout(&addr, timeout).map_err(|e| e.to_string())?;
+    stream
+        .set_read_timeout(Some(timeout))
+        .and_then(|_| stream.set_write_timeout(Some(timeout)))
+        .map_err(|e| e.to_string())?;
+
+    let request = format!(
+        "POST {} HTTP/1.1\r\nHost: {}\r\nUser-Agent: ffpb/{}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        url.path,
+        url.authority(),
+        env!("CARGO_PKG_VERSION"),
+        body.len()
+    );
+    stream
+        .write_all(request.as_bytes())
+        .map_err(|e| e.to_string())?;
+
+    // Only the status line is of interest, e.g. "HTTP/1.1 200 OK".
+    let mut head = [0u8; 64];
+    let mut len = 0;
+    while len < head.len() && !head[..len].contains(&b'\n') {
+        match stream.read(&mut head[len..]) {
+            Ok(0) => break,
+            Ok(n) => len += n,
+            Err(e) => return Err(e.to_string()),
+        }
+    }
+    String::from_utf8_lossy(&head[..len])
+        .split_whitespace()
+        .nth(1)
+        .and_then(|code| code.parse().ok())
+        .ok_or_else(|| "invalid response".to_string())
+}
+
+/// Read the request line and headers of an incoming request and return the
+/// method and path. Request bodies are not supported.
+pub fn read_request(stream: &mut TcpStream) -> Option<(String, String)> {
+    let mut head = Vec::new();
+    let mut buf = [0u8; 1024];
+    while !head.windows(4).any(|w| w == b"\r\n\r\n") {
+        match stream.read(&mut buf) {
+            Ok(0) | Err(_) => break,
+            Ok(n) => head.extend_from_slice(&buf[..n]),
+        }
+        if head.len() > 16 * 1024 {
+            return None;
+        }
+    }
+
+    let head = String::from_utf8_lossy(&head);
+    let mut parts = head.lines().next()?.split_whitespace();
+    let method = parts.next()?.to_string();
+    // Query strings are ignored.
+    let path = parts.next()?.split('?').next()?.to_string();
+    Some((method, path))
+}
+
+/// Write a complete response and close the connection.
+pub fn respond(stream: &mut TcpStream, status: &str, content_type: &str, body: &str) {
+    let response = format!(
+        "HTTP/1.1 {status}\r\nServer: ffpb/{}\r\nContent-Type: {content_type}\r\nContent-Length: {}\r\nCache-Control: no-store\r\nConnection: close\r\n\r\n{body}",
+        env!("CARGO_PKG_VERSION"),
+        body.len()
+    );
+    let _ = stream.write_all(response.as_bytes());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{net::TcpListener, thread, time::Instant};
+
+    #[test]
+    fn parses_urls() {
+        let url = Url::parse("http://example.com:8080/hooks/ffpb?x=1").unwrap();
+        assert_eq!(url.host, "example.com");
+        assert_eq!(url.port, 8080);
+        assert_eq!(url.path, "/hooks/ffpb?x=1");
+        assert_eq!(url.authority(), "example.com:8080");
+
+        let url = Url::parse("http://localhost").unwrap();
+        assert_eq!((url.port, url.path.as_str()), (80, "/"));
+
+        let url = Url::parse("http://[::1]:9000/x").unwrap();
+        assert_eq!((url.host.as_str(), url.port), ("::1", 9000));
+        assert_eq!(url.authority(), "[::1]:9000");
+        assert_eq!(Url::parse("http://[::1]/").unwrap().port, 80);
+    }
+
+    #[test]
+    fn rejects_invalid_urls() {
+        for url in [
+            "https://example.com/",
+            "example.com:80",
+            "http://",
+            "http://:80/",
+            "http://host:port/",
+            "http://host:99999/",
+            "http://[::1/",
+        ] {
+            assert!(Url::parse(url).is_none(), "{url}");
+        }
+    }
+
+    #[test]
+    fn times_out_on_silent_endpoint() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = Url::parse(&format!("http://{}/", listener.local_addr().unwrap())).unwrap();
+        // Accept the connection but never answer.
+        let _silent = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            thread::sleep(Duration::from_secs(2));
+            drop(stream);
+        });
+
+        let started = Instant::now();
+        let result = post_json(&url, "{}", Duration::from_millis(200));
+        assert!(result.is_err(), "{result:?}");
+        assert!(started.elapsed() < Duration::from_secs(1));
+    }
+}
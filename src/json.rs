@@ -0,0 +1,112 @@
+use std::fmt::Write;
+
+/// Builds a JSON object one field at a time.
+pub struct Object {
+    buf: String,
+}
+
+impl Object {
+    pub fn new() -> Self {
+        Self {
+            buf: String::from("{"),
+        }
+    }
+
+    pub fn str(self, key: &str, value: &str) -> Self {
+        let value = string(value);
+        self.raw(key, &value)
+    }
+
+    /// Non-finite numbers are written as `null`.
+    pub fn num(self, key: &str, value: f64) -> Self {
+        if value.is_finite() {
+            self.raw(key, &value.to_string())
+        } else {
+            self.raw(key, "null")
+        }
+    }
+
+    pub fn opt_num(self, key: &str, value: Option<f64>) -> Self {
+        match value {
+            Some(value) => self.num(key, value),
+            None => self.raw(key, "null"),
+        }
+    }
+
+    pub fn int(self, key: &str, value: i64) -> Self {
+        self.raw(key, &value.to_string())
+    }
+
+    /// Insert an already encoded value, e.g. a nested object or an array.
+    pub fn raw(mut self, key: &str, json: &str) -> Self {
+        if self.buf.len() > 1 {
+            self.buf.push(',');
+        }
+        self.buf.push_str(&string(key));
+        self.buf.push(':');
+        self.buf.push_str(json);
+        self
+    }
+
+    pub fn finish(mut self) -> String {
+        self.buf.push('}');
+        self.buf
+    }
+}
+
+/// A JSON array of strings.
+pub fn string_array<'a>(values: impl IntoIterator<Item = &'a str>) -> String {
+    let values = values.into_iter().map(string).collect::<Vec<_>>();
+    format!("[{}]", values.join(","))
+}
+
+/// A quoted and escaped JSON string.
+pub fn string(value: &str) -> String {
+    let mut buf = String::with_capacity(value.len() + 2);
+    buf.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => buf.push_str("\\\""),
+            '\\' => buf.push_str("\\\\"),
+            '\n' => buf.push_str("\\n"),
+            '\r' => buf.push_str("\\r"),
+            '\t' => buf.push_str("\\t"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(buf, "\\u{:04x}", c as u32);
+            }
+            c => buf.push(c),
+        }
+    }
+    buf.push('"');
+    buf
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn escapes_strings() {
+        assert_eq!(string("plain"), r#""plain""#);
+        assert_eq!(string(r#"a "quoted" \ path"#), r#""a \"quoted\" \\ path""#);
+        assert_eq!(string("line\nbreak\r\ttab"), r#""line\nbreak\r\ttab""#);
+        assert_eq!(string("\u{1}\u{1f}"), r#""\u0001\u001f""#);
+        assert_eq!(string("ünïcode ✓"), "\"ünïcode ✓\"");
+    }
+
+    #[test]
+    fn builds_objects() {
+        let json = Object::new()
+            .str("name", "x")
+            .int("n", -2)
+            .num("f", 1.5)
+            .num("nan", f64::NAN)
+            .opt_num("none", None)
+            .raw("list", &string_array(["a", "b"]))
+            .finish();
+        assert_eq!(
+            json,
+            r#"{"name":"x","n":-2,"f":1.5,"nan":null,"none":null,"list":["a","b"]}"#
+        );
+    }
+}
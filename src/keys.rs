@@ -0,0 +1,122 @@
+use std::{
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+    thread::{self, JoinHandle},
+};
+
+/// Puts the terminal into a mode where keys are delivered one by one without
+/// being echoed, and restores the previous mode when dropped.
+///
+/// Signal keys like Ctrl-C keep working.
+pub struct RawMode {
+    #[cfg(unix)]
+    saved: libc::termios,
+}
+
+impl RawMode {
+    /// Returns `None` if stdin is not a terminal or raw mode is unsupported.
+    #[cfg(unix)]
+    pub fn enable() -> Option<Self> {
+        use std::io::IsTerminal;
+
+        if !std::io::stdin().is_terminal() {
+            return None;
+        }
+
+        // SAFETY: termios is plain data and fd 0 is a terminal.
+        unsafe {
+            let mut saved = std::mem::zeroed::<libc::termios>();
+            if libc::tcgetattr(libc::STDIN_FILENO, &mut saved) != 0 {
+                return None;
+            }
+            let mut raw = saved;
+            raw.c_lflag &= !(libc::ICANON | libc::ECHO);
+            raw.c_cc[libc::VMIN] = 1;
+            raw.c_cc[libc::VTIME] = 0;
+            if libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &raw) != 0 {
+                return None;
+            }
+            Some(Self { saved })
+        }
+    }
+
+    #[cfg(not(unix))]
+    pub fn enable() -> Option<Self> {
+        None
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        #[cfg(unix)]
+        // SAFETY: restores the attributes read in `enable`.
+        unsafe {
+            libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &self.saved);
+        }
+    }
+}
+
+/// Background thread reading keys from stdin, stopped when dropped.
+pub struct KeyReader {
+    stop: Arc<AtomicBool>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl KeyReader {
+    /// Call `on_key` for every key pressed until dropped or `on_key` returns `false`.
+    pub fn spawn(on_key: impl Fn(u8) -> bool + Send + 'static) -> Self {
+        let stop = Arc::new(AtomicBool::new(false));
+        let handle = read_keys(Arc::clone(&stop), on_key);
+        Self {
+            stop,
+            handle: Some(handle),
+        }
+    }
+}
+
+impl Drop for KeyReader {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+#[cfg(unix)]
+fn read_keys(
+    stop: Arc<AtomicBool>,
+    on_key: impl Fn(u8) -> bool + Send + 'static,
+) -> JoinHandle<()> {
+    thread::spawn(move || {
+        while !stop.load(Ordering::SeqCst) {
+            let mut fds = libc::pollfd {
+                fd: libc::STDIN_FILENO,
+                events: libc::POLLIN,
+                revents: 0,
+            };
+            // SAFETY: polls a single valid pollfd with a short timeout.
+            let ready = unsafe { libc::poll(&mut fds, 1, 100) };
+            if ready <= 0 {
+                continue;
+            }
+
+            let mut key = 0u8;
+            // SAFETY: reads one byte into a valid buffer.
+            let n = unsafe { libc::read(libc::STDIN_FILENO, (&mut key as *mut u8).cast(), 1) };
+            if n != 1 || !on_key(key) {
+                break;
+            }
+        }
+    })
+}
+
+#[cfg(not(unix))]
+fn read_keys(
+    _stop: Arc<AtomicBool>,
+    _on_key: impl Fn(u8) -> bool + Send + 'static,
+) -> JoinHandle<()> {
+    thread::spawn(|| {})
+}
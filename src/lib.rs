@@ -2,8 +2,25 @@
 //! progress bar with encoding statistics.
 
 mod args;
+mod batch;
+mod config;
+mod dry_run;
+mod hooks;
+mod http;
+mod json;
+mod keys;
+mod log;
+mod pause;
 mod progress;
+mod region;
 mod runner;
+mod status;
+mod stderr;
+mod tee;
+mod transport;
+mod two_pass;
+mod verify;
+mod webhook;
 
 /// Run ffmpeg with a built-in progress bar.
 ///
@@ -18,7 +35,43 @@ mod runner;
 /// std::process::exit(code);
 /// ```
 pub fn run(args: &[String]) -> Result<i32, Error> {
-    runner::run_ffmpeg(&args::parse_args(args))
+    runner::run_ffmpeg(&args::parse_args(args)?, &runner::RunContext::default())
+}
+
+/// Run a batch of ffmpeg commands one after another, or with `-j <n>` several
+/// at once, showing the progress of every running job and of the whole batch,
+/// followed by a table of results.
+///
+/// Takes the arguments of `ffpb batch`: a job file with one ffmpeg command
+/// per line, or `--template '<ffmpeg arguments>'` followed by input files or
+/// globs. `{input}`, `{stem}` and `{dir}` in the template are replaced for
+/// every input. Other ffpb options apply to every job.
+///
+/// Returns 0 if every job succeeded and 1 otherwise.
+///
+/// # Example
+/// ```no_run
+/// let args = vec!["--template", "-i {input} -c:v libx264 {stem}.mp4", "*.mkv"]
+///     .into_iter().map(String::from).collect::<Vec<_>>();
+/// let code = ffpb::run_batch(&args).unwrap();
+/// std::process::exit(code);
+/// ```
+pub fn run_batch(args: &[String]) -> Result<i32, Error> {
+    batch::run_batch(args)
+}
+
+/// Print the effective ffpb options to stdout, in the configuration file
+/// format with the file, environment variable or command line each one comes
+/// from.
+///
+/// Options are read from `$XDG_CONFIG_HOME/ffpb/config.toml`, the nearest
+/// `.ffpb.toml`, `FFPB_*` environment variables and `args`, in increasing
+/// precedence. `--profile <name>` applies the `[profile.<name>]` tables.
+pub fn print_config(args: &[String]) -> Result<i32, Error> {
+    let mut config = config::Config::load(config::selected_profile(args).as_deref())?;
+    config.apply_args(&args::split_ffpb_options(args).0)?;
+    print!("{}", config.render());
+    Ok(0)
 }
 
 /// Error type for ffpb operations.
@@ -28,6 +81,28 @@ pub enum Error {
     SpawnFailed(std::io::Error),
     /// FFmpeg was not found in PATH.
     FfmpegNotFound,
+    /// Failed to open or write the `--log-file`.
+    LogFailed(std::io::Error),
+    /// An ffpb option was given an invalid or missing value.
+    InvalidOption(String),
+    /// FFmpeg made no progress for longer than `--stall-timeout` and was killed.
+    Stalled(std::time::Duration),
+    /// FFmpeg ran for longer than `--timeout` and was killed.
+    TimedOut(std::time::Duration),
+    /// FFmpeg succeeded but `--verify` found problems with the outputs.
+    VerifyFailed(Vec<String>),
+    /// The `--listen` address could not be bound.
+    ListenFailed(std::io::Error),
+}
+
+impl Error {
+    /// Exit code for the ffpb binary: 3 when verification failed, 1 otherwise.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Error::VerifyFailed(_) => 3,
+            _ => 1,
+        }
+    }
 }
 
 impl std::fmt::Display for Error {
@@ -35,6 +110,18 @@ impl std::fmt::Display for Error {
         match self {
             Error::SpawnFailed(e) => write!(f, "failed to spawn ffmpeg ({e})"),
             Error::FfmpegNotFound => write!(f, "ffmpeg not found in PATH"),
+            Error::LogFailed(e) => write!(f, "failed to open log file ({e})"),
+            Error::InvalidOption(msg) => write!(f, "{msg}"),
+            Error::Stalled(d) => write!(f, "ffmpeg stalled for {}s, terminated", d.as_secs()),
+            Error::TimedOut(d) => write!(
+                f,
+                "ffmpeg exceeded the {}s timeout, terminated",
+                d.as_secs()
+            ),
+            Error::VerifyFailed(problems) => {
+                write!(f, "output verification failed: {}", problems.join("; "))
+            }
+            Error::ListenFailed(e) => write!(f, "failed to listen for status requests ({e})"),
         }
     }
 }
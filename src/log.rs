@@ -0,0 +1,100 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// Number of rotated files (`<path>.1` .. `<path>.N`) kept next to the log.
+const LOG_KEEP: usize = 3;
+
+/// Append-only log file with timestamped lines and optional size-based rotation.
+pub struct LogFile {
+    path: PathBuf,
+    file: File,
+    written: u64,
+    max_size: Option<u64>,
+}
+
+impl LogFile {
+    pub fn open(path: &Path, max_size: Option<u64>) -> io::Result<Self> {
+        if let Some(parent) = path.parent()
+            && !parent.as_os_str().is_empty()
+        {
+            fs::create_dir_all(parent)?;
+        }
+
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let written = file.metadata()?.len();
+
+        Ok(Self {
+            path: path.to_path_buf(),
+            file,
+            written,
+            max_size,
+        })
+    }
+
+    pub fn line(&mut self, line: &str) {
+        let line = format!("[{}] {}\n", timestamp(), line.trim_end_matches('\r'));
+
+        if let Some(max) = self.max_size
+            && self.written > 0
+            && self.written + line.len() as u64 > max
+        {
+            let _ = self.rotate();
+        }
+
+        if self.file.write_all(line.as_bytes()).is_ok() {
+            self.written += line.len() as u64;
+        }
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        let rotated = |n: usize| {
+            let mut name = self.path.clone().into_os_string();
+            name.push(format!(".{n}"));
+            PathBuf::from(name)
+        };
+
+        for n in (1..LOG_KEEP).rev() {
+            let from = rotated(n);
+            if from.exists() {
+                fs::rename(&from, rotated(n + 1))?;
+            }
+        }
+        fs::rename(&self.path, rotated(1))?;
+
+        self.file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        self.written = 0;
+        Ok(())
+    }
+}
+
+/// Current UTC time as `YYYY-MM-DDTHH:MM:SS.mmmZ`.
+pub fn timestamp() -> String {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    let secs = now.as_secs();
+    let (hours, mins, secs_of_min) = ((secs / 3600) % 24, (secs / 60) % 60, secs % 60);
+
+    // Days since epoch to a civil date (Howard Hinnant's algorithm).
+    let z = (secs / 86400) as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!(
+        "{year:04}-{month:02}-{day:02}T{hours:02}:{mins:02}:{secs_of_min:02}.{:03}Z",
+        now.subsec_millis()
+    )
+}
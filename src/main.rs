@@ -3,16 +3,57 @@ fn main() {
 
     if args.iter().any(|a| a == "-h" || a == "--help") {
         eprintln!("ffmpeg with a progress bar.\n",);
-        eprintln!("\x1b[1mUsage:\x1b[0m ffpb [ffmpeg arguments...]\n");
+        eprintln!("\x1b[1mUsage:\x1b[0m ffpb [ffmpeg arguments...]");
+        eprintln!("       ffpb batch [-j <n>] <job-file> [options]");
+        eprintln!(
+            "       ffpb batch [-j <n>] --template <ffmpeg arguments> <inputs...> [options]\n"
+        );
         eprintln!("\x1b[1mOptions:\x1b[0m");
-        eprintln!("  --clean          Only show progress bar, suppress ffmpeg output");
-        eprintln!("  -h, --help       Show this help");
-        eprintln!("  -V, --version    Show ffpb version\n");
+        eprintln!("  --clean                Only show progress bar, suppress ffmpeg output");
+        eprintln!("  --per-output           Show quality and size of each output file");
+        eprintln!("  --color <when>         Color ffmpeg output: auto, always or never");
+        eprintln!("  --pretty               Condense stream info into compact tables");
+        eprintln!("  --overwrite <mode>     Existing outputs: ask, always (-y) or never (-n)");
+        eprintln!("  --no-keys              Disable keyboard controls while encoding");
+        eprintln!("  --refresh <t>          Redraw the progress bar at most every <t> (default 1)");
+        eprintln!("  --two-pass             Run pass 1 and pass 2 of a two-pass encode");
+        eprintln!("  --verify               Check outputs with ffprobe, exit 3 on mismatch");
+        eprintln!("  --stall-after <t>      Warn when progress stands still for <t> (default 30)");
+        eprintln!("  --stall-timeout <t>    Terminate ffmpeg when progress stands still for <t>");
+        eprintln!("  --timeout <t>          Terminate ffmpeg after <t> of total wall-clock time");
+        eprintln!("  --retries <n>          Rerun ffmpeg up to <n> times on transient failures");
+        eprintln!("  --on-success <cmd>     Run a shell command when ffmpeg succeeds");
+        eprintln!("  --on-failure <cmd>     Run a shell command when ffmpeg fails");
+        eprintln!("  --on-finish <cmd>      Run a shell command when ffmpeg exits");
+        eprintln!("  --webhook <url>        POST JSON events about the encode to an http url");
+        eprintln!("  --webhook-every <p>    Send a progress event every <p> percent (default 10)");
+        eprintln!("  --listen <addr>        Serve status page, JSON and metrics on <addr>");
+        eprintln!("  --progress-via <t>     Read progress via pipe, unix, tcp or stdout");
+        eprintln!("  --log-file <path>      Append timestamped ffmpeg stderr to <path>");
+        eprintln!("  --log-dir <dir>        Log to <dir>/<output>.log unless --log-file is given");
+        eprintln!("  --log-max-size <n>     Rotate the log file after <n> bytes (e.g. 10M)");
+        eprintln!("  -j, --jobs <n>         Run <n> batch jobs in parallel");
+        eprintln!("  --ffmpeg <path>        ffmpeg binary to run (default: ffmpeg from PATH)");
+        eprintln!("  --verbose              Report the ffmpeg binary and version in use");
+        eprintln!("  --preset <name>        Insert the arguments of a configured preset");
+        eprintln!("  --dry-run              Print the ffmpeg command and expectations, then exit");
+        eprintln!("  --profile <name>       Apply a [profile.<name>] from the config files");
+        eprintln!("  --print-config         Show the effective options and where they come from");
+        eprintln!("  -h, --help             Show this help");
+        eprintln!("  -V, --version          Show ffpb version\n");
         eprintln!("\x1b[1mExamples:\x1b[0m");
         eprintln!("  ffpb -i input.mp4 -c:v libx264 output.mp4");
         eprintln!("  ffpb -ss 10 -to 20 -i input.mp4 output.mp4");
-        eprintln!("  ffpb -y -i input.mp4 -c:a aac output.m4a\n");
+        eprintln!("  ffpb -y -i input.mp4 -c:a aac output.m4a");
+        eprintln!("  ffpb --two-pass -i input.mp4 -c:v libx264 -b:v 2M output.mp4");
+        eprintln!("  ffpb --preset web-1080p -i input.mov output.mp4");
+        eprintln!("  ffpb batch --template '-i {{input}} -c:v libx264 {{stem}}.mp4' '*.mkv'\n");
         eprintln!("All other arguments are forwarded directly to ffmpeg.");
+        eprintln!(
+            "Every option can also be set as FFPB_<OPTION>, e.g. FFPB_CLEAN=1 or FFPB_FFMPEG=<path>."
+        );
+        eprintln!("Defaults are read from ~/.config/ffpb/config.toml and the nearest .ffpb.toml.");
+        eprintln!("Turn off a switch they turn on with --<switch>=false, e.g. --clean=false.");
         return;
     }
     if args.iter().any(|a| a == "-V" || a == "--version") {
@@ -20,9 +61,21 @@ fn main() {
         return;
     }
 
-    let code = ffpb::run(&args).unwrap_or_else(|e| {
+    if args.iter().any(|a| a == "--print-config") {
+        let code = ffpb::print_config(&args).unwrap_or_else(|e| {
+            eprintln!("\x1b[1;31m[ERROR]\x1b[0m {e}");
+            e.exit_code()
+        });
+        std::process::exit(code);
+    }
+
+    let result = match args.first().map(String::as_str) {
+        Some("batch") => ffpb::run_batch(&args[1..]),
+        _ => ffpb::run(&args),
+    };
+    let code = result.unwrap_or_else(|e| {
         eprintln!("\x1b[1;31m[ERROR]\x1b[0m {e}");
-        1
+        e.exit_code()
     });
     std::process::exit(code);
 }
@@ -0,0 +1,53 @@
+use std::{
+    process::Child,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+static TOGGLE_REQUESTS: AtomicUsize = AtomicUsize::new(0);
+
+/// Ask every running ffmpeg to pause or resume on its runner's next tick.
+pub fn request_toggle() {
+    TOGGLE_REQUESTS.fetch_add(1, Ordering::SeqCst);
+}
+
+/// Whether ffmpeg should be paused right now. Every request flips this for
+/// all runs, so parallel jobs follow along and a job started while the others
+/// are paused starts paused too.
+pub fn requested() -> bool {
+    TOGGLE_REQUESTS.load(Ordering::SeqCst) % 2 == 1
+}
+
+/// Toggle pause on `SIGUSR1`, e.g. `kill -USR1 <ffpb pid>`.
+#[cfg(unix)]
+pub fn install_signal_handler() {
+    extern "C" fn on_sigusr1(_: libc::c_int) {
+        request_toggle();
+    }
+
+    let handler: extern "C" fn(libc::c_int) = on_sigusr1;
+    // SAFETY: the handler only increments an atomic, which is async-signal-safe.
+    unsafe {
+        libc::signal(libc::SIGUSR1, handler as libc::sighandler_t);
+    }
+}
+
+#[cfg(not(unix))]
+pub fn install_signal_handler() {}
+
+/// Suspend (`SIGSTOP`) or resume (`SIGCONT`) ffmpeg. Returns `false` if the
+/// signal could not be delivered or the platform does not support it.
+#[cfg(unix)]
+pub fn set_suspended(child: &Child, suspended: bool) -> bool {
+    let signal = if suspended {
+        libc::SIGSTOP
+    } else {
+        libc::SIGCONT
+    };
+    // SAFETY: plain syscall on the pid of a child we own and have not reaped.
+    unsafe { libc::kill(child.id() as libc::pid_t, signal) == 0 }
+}
+
+#[cfg(not(unix))]
+pub fn set_suspended(_child: &Child, _suspended: bool) -> bool {
+    false
+}
@@ -1,31 +1,90 @@
+use crate::{args::is_local_file, region::Region};
 use std::{
+    collections::BTreeMap,
     fmt::Write as FmtWrite,
-    io::{self, Write},
-    time::Instant,
+    fs,
+    path::Path,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
 };
 
 const BAR_WIDTH: usize = 40;
-const PB_START: (u8, u8, u8) = (168, 85, 247);
+pub const PB_START: (u8, u8, u8) = (168, 85, 247);
 const PB_END: (u8, u8, u8) = (236, 72, 153);
 const DIM_COLOR: (u8, u8, u8) = (55, 65, 81);
-const DONE_COLOR: (u8, u8, u8) = (75, 181, 67);
+pub const DONE_COLOR: (u8, u8, u8) = (75, 181, 67);
+pub const WARN_COLOR: (u8, u8, u8) = (245, 158, 11);
+pub const ERROR_COLOR: (u8, u8, u8) = (239, 68, 68);
 
-fn fg(buf: &mut String, r: u8, g: u8, b: u8) {
+pub fn fg(buf: &mut String, r: u8, g: u8, b: u8) {
     let _ = write!(buf, "\x1b[38;2;{r};{g};{b}m");
 }
 
-fn bold(buf: &mut String) {
+pub fn bold(buf: &mut String) {
     buf.push_str("\x1b[1m");
 }
 
-fn dim(buf: &mut String) {
+pub fn dim(buf: &mut String) {
     buf.push_str("\x1b[2m");
 }
 
-fn reset(buf: &mut String) {
+pub fn reset(buf: &mut String) {
     buf.push_str("\x1b[0m");
 }
 
+/// Show a dup/drop counter in the warning color, bold while it is increasing.
+fn frame_counter(buf: &mut String, count: u64, last: u64, label: &str, finished: bool) {
+    if count == 0 {
+        return;
+    }
+
+    dim(buf);
+    buf.push_str(" • ");
+    reset(buf);
+    fg(buf, WARN_COLOR.0, WARN_COLOR.1, WARN_COLOR.2);
+    if count > last && !finished {
+        bold(buf);
+        let _ = write!(buf, "⚠ {count} {label}");
+    } else {
+        let _ = write!(buf, "{count} {label}");
+    }
+    reset(buf);
+}
+
+/// e.g. `job 2/5 ██████░░░░░░ 34.0% • eta 12m3s`
+pub fn render_overall(buf: &mut String, label: &str, fraction: f64, started_at: Instant) {
+    const WIDTH: usize = 20;
+
+    let filled = ((fraction * WIDTH as f64).round() as usize).min(WIDTH);
+
+    bold(buf);
+    let _ = write!(buf, "{label} ");
+    reset(buf);
+    for i in 0..WIDTH {
+        if i < filled {
+            let (r, g, b) = lerp_color(i as f64 / (WIDTH - 1) as f64, PB_START, PB_END);
+            fg(buf, r, g, b);
+            buf.push('█');
+        } else {
+            fg(buf, DIM_COLOR.0, DIM_COLOR.1, DIM_COLOR.2);
+            buf.push('░');
+        }
+    }
+    reset(buf);
+    let _ = write!(buf, " {:.1}%", fraction * 100.0);
+
+    if fraction > 0.0 && fraction < 1.0 {
+        let elapsed_us = started_at.elapsed().as_micros() as f64;
+        let eta_us = (elapsed_us * (1.0 - fraction) / fraction) as u64;
+        dim(buf);
+        buf.push_str(" • ");
+        reset(buf);
+        fg(buf, PB_START.0, PB_START.1, PB_START.2);
+        let _ = write!(buf, "eta {}", format_time(eta_us));
+        reset(buf);
+    }
+}
+
 fn lerp_color(t: f64, from: (u8, u8, u8), to: (u8, u8, u8)) -> (u8, u8, u8) {
     let r = from.0 as f64 + (to.0 as f64 - from.0 as f64) * t;
     let g = from.1 as f64 + (to.1 as f64 - from.1 as f64) * t;
@@ -33,7 +92,7 @@ fn lerp_color(t: f64, from: (u8, u8, u8), to: (u8, u8, u8)) -> (u8, u8, u8) {
     (r as u8, g as u8, b as u8)
 }
 
-fn format_size(bytes: u64) -> String {
+pub fn format_size(bytes: u64) -> String {
     const KIB: f64 = 1024.0;
     const MIB: f64 = 1024.0 * 1024.0;
     const GIB: f64 = 1024.0 * 1024.0 * 1024.0;
@@ -50,6 +109,14 @@ fn format_size(bytes: u64) -> String {
     }
 }
 
+/// Size of an output file on disk, if it is a local path.
+fn output_size(output: &str) -> Option<u64> {
+    if !is_local_file(output) {
+        return None;
+    }
+    fs::metadata(output).ok().map(|m| m.len())
+}
+
 pub fn format_time(us: u64) -> String {
     let total_secs = us / 1_000_000;
     let hours = total_secs / 3600;
@@ -78,40 +145,200 @@ pub fn format_time_clock(us: u64) -> String {
     }
 }
 
-#[derive(Default)]
+/// Position of the current job within a batch, rendered as an extra line
+/// below the bar with the progress and ETA of the whole batch.
+#[derive(Clone, Copy)]
+pub struct Overall {
+    /// Index of the current job, starting at 0.
+    pub job: usize,
+    pub jobs: usize,
+    pub started_at: Instant,
+}
+
+/// One of several ffmpeg runs shown as a single bar, e.g. a pass of a
+/// two-pass encode. The run fills its own section of the bar.
+#[derive(Clone)]
+pub struct Stage {
+    /// Shown on the bar, e.g. `pass 1/2`.
+    pub label: String,
+    /// Part of the bar already filled when this run starts.
+    pub start: f64,
+    /// Part of the bar this run fills.
+    pub weight: f64,
+    /// When the first run started, for the ETA of the whole bar.
+    pub started_at: Instant,
+    /// Time spent paused since the first run started, shared by all runs.
+    pub paused: Arc<Mutex<Duration>>,
+    /// Whether the bar is left on screen when this run finishes.
+    pub last: bool,
+}
+
+impl Stage {
+    /// Position on the whole bar of a fraction of this run.
+    pub fn fraction(&self, fraction: f64) -> f64 {
+        self.start + self.weight * fraction
+    }
+
+    /// Time since the first run started, excluding the time spent paused.
+    fn elapsed(&self, paused_at: Option<Instant>) -> Duration {
+        let paused =
+            *self.paused.lock().unwrap() + paused_at.map_or(Duration::ZERO, |at| at.elapsed());
+        self.started_at.elapsed().saturating_sub(paused)
+    }
+}
+
+#[derive(Clone, Default)]
 pub struct ProgressStats {
     pub frame: u64,
     pub fps: f64,
-    pub bitrate_kbps: f64,
+    /// `None` when ffmpeg reports `N/A`.
+    pub bitrate_kbps: Option<f64>,
     pub total_size: u64,
     pub out_time_us: u64,
+    pub out_time_ms: u64,
+    pub out_time: String,
+    pub dup_frames: u64,
+    pub drop_frames: u64,
     pub speed: f64,
+    /// Quality of the first stream of the first output (`stream_0_0_q`).
     pub q: f64,
+    /// Quality of every output stream, keyed by `(file, stream)` index.
+    pub stream_q: BTreeMap<(usize, usize), f64>,
+    /// Keys not understood by ffpb, kept verbatim.
+    pub extra: BTreeMap<String, String>,
     pub is_end: bool,
 }
 
 pub struct ProgressBar {
     total_duration_us: Option<u64>,
     last_render: Option<Instant>,
+    refresh: Duration,
     started_at: Instant,
-    lines_rendered: usize,
+    region: Region,
+    slot: usize,
     pulse_frame: usize,
     compact: bool,
+    last_dup_frames: u64,
+    last_drop_frames: u64,
+    outputs: Vec<String>,
+    stalled_for: Option<Duration>,
+    paused_at: Option<Instant>,
+    paused_total: Duration,
+    show_stats: bool,
+    footer: Option<String>,
+    label: Option<String>,
+    attempt: Option<(u32, u32)>,
+    stage: Option<Stage>,
+    overall: Option<Overall>,
 }
 
 impl ProgressBar {
     pub fn new(total_duration_us: Option<u64>, compact: bool) -> Self {
-        eprint!("\x1b[?25l");
+        Self::in_region(total_duration_us, compact, &Region::default())
+    }
+
+    /// A bar drawn in a region shared with other bars, below the ones
+    /// already there.
+    pub fn in_region(total_duration_us: Option<u64>, compact: bool, region: &Region) -> Self {
         Self {
             total_duration_us,
             last_render: None,
+            refresh: Duration::from_secs(1),
             started_at: Instant::now(),
-            lines_rendered: 0,
+            region: region.clone(),
+            slot: region.add_slot(),
             pulse_frame: 0,
             compact,
+            last_dup_frames: 0,
+            last_drop_frames: 0,
+            outputs: Vec::new(),
+            stalled_for: None,
+            paused_at: None,
+            paused_total: Duration::ZERO,
+            show_stats: true,
+            footer: None,
+            label: None,
+            attempt: None,
+            stage: None,
+            overall: None,
+        }
+    }
+
+    pub fn set_overall(&mut self, overall: Option<Overall>) {
+        self.overall = overall;
+    }
+
+    /// Shown at the start of the first line, e.g. the output of a job.
+    pub fn set_label(&mut self, label: Option<String>) {
+        self.label = label;
+        self.last_render = None;
+    }
+
+    /// Show which of several attempts this is, e.g. `attempt 2/3`.
+    pub fn set_attempt(&mut self, attempt: Option<(u32, u32)>) {
+        self.attempt = attempt;
+        self.last_render = None;
+    }
+
+    pub fn set_stage(&mut self, stage: Option<Stage>) {
+        self.stage = stage;
+        self.last_render = None;
+    }
+
+    /// Show or hide the stats line below the bar.
+    pub fn toggle_stats(&mut self) {
+        self.show_stats = !self.show_stats;
+        self.last_render = None;
+    }
+
+    /// A dimmed line rendered beneath the bar while encoding, e.g. key hints.
+    pub fn set_footer(&mut self, footer: Option<String>) {
+        self.footer = footer;
+        self.last_render = None;
+    }
+
+    /// Show the paused state and stop the elapsed time (and so the ETA) from
+    /// advancing while ffmpeg is suspended.
+    pub fn set_paused(&mut self, paused: bool) {
+        match (self.paused_at, paused) {
+            (None, true) => self.paused_at = Some(Instant::now()),
+            (Some(at), false) => {
+                self.paused_total += at.elapsed();
+                if let Some(ref stage) = self.stage {
+                    *stage.paused.lock().unwrap() += at.elapsed();
+                }
+                self.paused_at = None;
+            }
+            _ => return,
+        }
+        self.last_render = None;
+    }
+
+    /// Time spent encoding, excluding any time spent paused.
+    fn elapsed(&self) -> Duration {
+        let paused = self.paused_total + self.paused_at.map_or(Duration::ZERO, |at| at.elapsed());
+        self.started_at.elapsed().saturating_sub(paused)
+    }
+
+    /// Show a warning that progress has not advanced for the given time.
+    pub fn set_stalled(&mut self, stalled_for: Option<Duration>) {
+        let changed = self.stalled_for.is_some() != stalled_for.is_some();
+        self.stalled_for = stalled_for;
+        if changed {
+            self.last_render = None;
         }
     }
 
+    /// Render one extra line per output file with its own quality and size.
+    pub fn show_outputs(&mut self, outputs: Vec<String>) {
+        self.outputs = outputs;
+    }
+
+    /// Redraw at most once per `refresh`, unless forced.
+    pub fn set_refresh(&mut self, refresh: Duration) {
+        self.refresh = refresh;
+    }
+
     pub fn set_total_duration(&mut self, us: u64) {
         self.total_duration_us = Some(us);
     }
@@ -119,7 +346,7 @@ impl ProgressBar {
     pub fn update(&mut self, stats: &ProgressStats, force: bool) {
         if !force
             && let Some(last) = self.last_render
-            && last.elapsed().as_millis() < 1000
+            && last.elapsed() < self.refresh
         {
             return;
         }
@@ -128,36 +355,50 @@ impl ProgressBar {
         self.render(stats, false);
     }
 
+    /// Leave the final state of the bar on screen, unless another stage
+    /// continues it.
     pub fn finish(&mut self, stats: &ProgressStats) {
-        self.render(stats, true);
-        self.lines_rendered = 0;
-        eprint!("\x1b[?25h");
-        let _ = io::stderr().flush();
+        if self.stage.as_ref().is_some_and(|s| !s.last) {
+            self.region.remove(self.slot);
+        } else {
+            self.render(stats, true);
+        }
     }
 
-    pub fn interrupt(&mut self) {
-        self.clear_lines();
-        eprint!("\x1b[?25h");
-        let _ = io::stderr().flush();
+    /// Print a line above the bar.
+    pub fn print_above(&mut self, line: &str) {
+        self.region.print_above(line);
     }
 
-    fn clear_lines(&self) {
-        let mut stderr = io::stderr().lock();
-        let _ = write!(stderr, "\x1b[2K");
-        for _ in 1..self.lines_rendered {
-            let _ = write!(stderr, "\x1b[A\x1b[2K");
-        }
-        let _ = write!(stderr, "\r");
-        let _ = stderr.flush();
+    /// Erase the bar.
+    pub fn interrupt(&mut self) {
+        self.region.remove(self.slot);
     }
 
     fn render(&mut self, stats: &ProgressStats, finished: bool) {
-        self.clear_lines();
         let mut buf = String::with_capacity(100);
         let indent = if self.compact { "" } else { "  " };
 
         buf.push_str(indent);
 
+        if let Some(ref label) = self.label {
+            fg(&mut buf, PB_START.0, PB_START.1, PB_START.2);
+            buf.push_str(label);
+            reset(&mut buf);
+            dim(&mut buf);
+            buf.push_str(" • ");
+            reset(&mut buf);
+        }
+
+        if let Some(ref stage) = self.stage {
+            bold(&mut buf);
+            buf.push_str(&stage.label);
+            reset(&mut buf);
+            dim(&mut buf);
+            buf.push_str(" • ");
+            reset(&mut buf);
+        }
+
         if finished {
             fg(&mut buf, DONE_COLOR.0, DONE_COLOR.1, DONE_COLOR.2);
             bold(&mut buf);
@@ -176,8 +417,39 @@ impl ProgressBar {
                 let _ = write!(buf, "/{}", format_time_clock(total));
             }
 
-            let elapsed_us = self.started_at.elapsed().as_micros() as u64;
+            let elapsed_us = self.elapsed().as_micros() as u64;
             let _ = write!(buf, " in {}", format_time(elapsed_us));
+
+            if let Some((attempt, attempts)) = self.attempt {
+                dim(&mut buf);
+                buf.push_str(" • ");
+                reset(&mut buf);
+                fg(&mut buf, WARN_COLOR.0, WARN_COLOR.1, WARN_COLOR.2);
+                let _ = write!(buf, "attempt {attempt}/{attempts}");
+                reset(&mut buf);
+            }
+
+            if self.paused_at.is_some() {
+                dim(&mut buf);
+                buf.push_str(" • ");
+                reset(&mut buf);
+                fg(&mut buf, WARN_COLOR.0, WARN_COLOR.1, WARN_COLOR.2);
+                bold(&mut buf);
+                buf.push_str("⏸ paused");
+                reset(&mut buf);
+            } else if let Some(stalled_for) = self.stalled_for {
+                dim(&mut buf);
+                buf.push_str(" • ");
+                reset(&mut buf);
+                fg(&mut buf, WARN_COLOR.0, WARN_COLOR.1, WARN_COLOR.2);
+                bold(&mut buf);
+                let _ = write!(
+                    buf,
+                    "⚠ stalled for {}",
+                    format_time(stalled_for.as_micros() as u64)
+                );
+                reset(&mut buf);
+            }
         }
 
         buf.push('\n');
@@ -196,6 +468,10 @@ impl ProgressBar {
                 }
             }
         };
+        let progress_fraction = match self.stage {
+            Some(ref stage) => stage.fraction(progress_fraction),
+            None => progress_fraction,
+        };
 
         if self.total_duration_us.is_none() && !finished {
             let pulse_width = 7;
@@ -245,9 +521,18 @@ impl ProgressBar {
                 && stats.out_time_us > 0
                 && stats.out_time_us < total
             {
-                let eta_us = (self.started_at.elapsed().as_micros() as f64
-                    * (total.saturating_sub(stats.out_time_us) as f64 / stats.out_time_us as f64))
-                    as u64;
+                let eta_us = match self.stage {
+                    Some(ref stage) => {
+                        (stage.elapsed(self.paused_at).as_micros() as f64
+                            * (1.0 - progress_fraction)
+                            / progress_fraction) as u64
+                    }
+                    None => {
+                        (self.elapsed().as_micros() as f64
+                            * (total.saturating_sub(stats.out_time_us) as f64
+                                / stats.out_time_us as f64)) as u64
+                    }
+                };
                 dim(&mut buf);
                 buf.push_str(" • ");
                 reset(&mut buf);
@@ -257,46 +542,135 @@ impl ProgressBar {
             }
         }
 
+        if self.show_stats || finished {
+            self.render_stats(&mut buf, stats, finished, indent);
+        }
+
+        if !finished && let Some(overall) = self.overall {
+            let fraction = (overall.job as f64 + progress_fraction) / overall.jobs.max(1) as f64;
+            buf.push('\n');
+            buf.push_str(indent);
+            render_overall(
+                &mut buf,
+                &format!("job {}/{}", overall.job + 1, overall.jobs),
+                fraction,
+                overall.started_at,
+            );
+        }
+
+        if !finished && let Some(ref footer) = self.footer {
+            buf.push('\n');
+            buf.push_str(indent);
+            dim(&mut buf);
+            buf.push_str(footer);
+            reset(&mut buf);
+        }
+
+        let lines = buf.split('\n').map(String::from).collect();
+        if finished {
+            self.region.finish(self.slot, lines);
+        } else {
+            self.region.set(self.slot, lines);
+        }
+    }
+
+    /// Stats line and the per-output lines below it.
+    fn render_stats(
+        &mut self,
+        buf: &mut String,
+        stats: &ProgressStats,
+        finished: bool,
+        indent: &str,
+    ) {
         buf.push('\n');
         buf.push_str(indent);
 
         let _ = write!(buf, "{}", stats.frame);
-        dim(&mut buf);
+        dim(buf);
         buf.push_str(" @ ");
-        reset(&mut buf);
+        reset(buf);
         let _ = write!(buf, "{:.1} fps", stats.fps);
-        dim(&mut buf);
+        dim(buf);
         buf.push_str(" • ");
-        reset(&mut buf);
-        let _ = write!(buf, "{:.1}q", stats.q);
-        dim(&mut buf);
+        reset(buf);
+        if stats.stream_q.len() > 1 {
+            let qs = stats
+                .stream_q
+                .values()
+                .map(|q| format!("{q:.1}"))
+                .collect::<Vec<_>>();
+            let _ = write!(buf, "{}q", qs.join("/"));
+        } else {
+            let _ = write!(buf, "{:.1}q", stats.q);
+        }
+        dim(buf);
         buf.push_str(" • ");
-        reset(&mut buf);
+        reset(buf);
         let _ = write!(buf, "{}", format_size(stats.total_size));
-        dim(&mut buf);
+        dim(buf);
         buf.push_str(" • ");
-        reset(&mut buf);
-        let _ = write!(buf, "{:.1} kbps", stats.bitrate_kbps);
-        dim(&mut buf);
+        reset(buf);
+        match stats.bitrate_kbps {
+            Some(kbps) => {
+                let _ = write!(buf, "{kbps:.1} kbps");
+            }
+            None => buf.push_str("N/A kbps"),
+        }
+        dim(buf);
         buf.push_str(" • ");
-        reset(&mut buf);
+        reset(buf);
         let _ = write!(buf, "{:.1}x", stats.speed);
 
-        self.lines_rendered = 3;
-
-        if finished {
+        frame_counter(buf, stats.dup_frames, self.last_dup_frames, "dup", finished);
+        frame_counter(
+            buf,
+            stats.drop_frames,
+            self.last_drop_frames,
+            "drop",
+            finished,
+        );
+        self.last_dup_frames = stats.dup_frames;
+        self.last_drop_frames = stats.drop_frames;
+
+        for (index, output) in self.outputs.iter().enumerate() {
             buf.push('\n');
-        }
+            buf.push_str(indent);
+            dim(buf);
+            buf.push_str("↳ ");
+            reset(buf);
+
+            let name = Path::new(output)
+                .file_name()
+                .and_then(|n| n.to_str())
+                .unwrap_or(output);
+            fg(buf, PB_START.0, PB_START.1, PB_START.2);
+            buf.push_str(name);
+            reset(buf);
+
+            let qs = stats
+                .stream_q
+                .range((index, 0)..(index + 1, 0))
+                .map(|(_, q)| format!("{q:.1}"))
+                .collect::<Vec<_>>();
+            if !qs.is_empty() {
+                dim(buf);
+                buf.push_str(" • ");
+                reset(buf);
+                let _ = write!(buf, "{}q", qs.join("/"));
+            }
 
-        let mut stderr = io::stderr().lock();
-        let _ = write!(stderr, "{buf}");
-        let _ = stderr.flush();
+            if let Some(size) = output_size(output) {
+                dim(buf);
+                buf.push_str(" • ");
+                reset(buf);
+                buf.push_str(&format_size(size));
+            }
+        }
     }
 }
 
 impl Drop for ProgressBar {
     fn drop(&mut self) {
-        eprint!("\x1b[?25h");
-        let _ = io::stderr().flush();
+        self.region.remove(self.slot);
     }
 }
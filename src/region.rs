@@ -0,0 +1,135 @@
+use std::{
+    io::{self, Write},
+    sync::{Arc, Mutex},
+};
+
+/// The bottom part of the terminal where one or more bars are drawn.
+///
+/// Every bar owns a slot. Changing a slot, or printing a line above the
+/// region, redraws the whole region with a single write so bars from
+/// several threads never flicker or interleave with other output.
+#[derive(Clone, Default)]
+pub struct Region {
+    inner: Arc<Mutex<Inner>>,
+}
+
+#[derive(Default)]
+struct Inner {
+    /// Slot ids and their lines, top to bottom.
+    slots: Vec<(usize, Vec<String>)>,
+    /// Number of slots at the end of `slots` kept below all others.
+    bottom: usize,
+    next_id: usize,
+    lines_drawn: usize,
+    cursor_hidden: bool,
+}
+
+impl Region {
+    /// Reserve an empty slot below the existing ones, but above those added
+    /// with [`Region::add_bottom_slot`].
+    pub fn add_slot(&self) -> usize {
+        let mut inner = self.lock();
+        let id = inner.next_id;
+        inner.next_id += 1;
+        let at = inner.slots.len() - inner.bottom;
+        inner.slots.insert(at, (id, Vec::new()));
+        id
+    }
+
+    /// Reserve an empty slot which stays at the bottom, e.g. for a summary
+    /// of the bars above it.
+    pub fn add_bottom_slot(&self) -> usize {
+        let mut inner = self.lock();
+        let id = inner.next_id;
+        inner.next_id += 1;
+        inner.slots.push((id, Vec::new()));
+        inner.bottom += 1;
+        id
+    }
+
+    /// Replace the lines of a slot and redraw.
+    pub fn set(&self, slot: usize, lines: Vec<String>) {
+        let mut inner = self.lock();
+        if let Some((_, old)) = inner.slots.iter_mut().find(|(id, _)| *id == slot) {
+            *old = lines;
+        }
+        inner.draw(None);
+    }
+
+    /// Print the final lines of a slot above the region, where they stay, and
+    /// release the slot.
+    pub fn finish(&self, slot: usize, lines: Vec<String>) {
+        let mut inner = self.lock();
+        inner.release(slot);
+        let mut text = lines.join("\n");
+        text.push('\n');
+        inner.draw(Some(&text));
+    }
+
+    /// Release a slot and erase its lines.
+    pub fn remove(&self, slot: usize) {
+        let mut inner = self.lock();
+        if inner.release(slot) {
+            inner.draw(None);
+        }
+    }
+
+    /// Print a line above the region.
+    pub fn print_above(&self, line: &str) {
+        let mut text = line.to_string();
+        text.push('\n');
+        self.lock().draw(Some(&text));
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Inner> {
+        self.inner.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl Inner {
+    fn release(&mut self, slot: usize) -> bool {
+        let Some(pos) = self.slots.iter().position(|(id, _)| *id == slot) else {
+            return false;
+        };
+        if pos >= self.slots.len() - self.bottom {
+            self.bottom -= 1;
+        }
+        self.slots.remove(pos);
+        true
+    }
+
+    fn draw(&mut self, above: Option<&str>) {
+        let mut buf = String::new();
+
+        // Back to the first line of the region and clear everything below.
+        if self.lines_drawn > 1 {
+            buf.push_str(&format!("\x1b[{}A", self.lines_drawn - 1));
+        }
+        buf.push_str("\r\x1b[J");
+
+        if let Some(above) = above {
+            buf.push_str(above);
+        }
+
+        let lines = self
+            .slots
+            .iter()
+            .flat_map(|(_, lines)| lines)
+            .map(String::as_str)
+            .collect::<Vec<_>>();
+        buf.push_str(&lines.join("\n"));
+        self.lines_drawn = lines.len();
+
+        if !lines.is_empty() && !self.cursor_hidden {
+            buf.insert_str(0, "\x1b[?25l");
+            self.cursor_hidden = true;
+        } else if lines.is_empty() && self.cursor_hidden {
+            buf.push_str("\x1b[?25h");
+            self.cursor_hidden = false;
+        }
+
+        let mut stderr = io::stderr().lock();
+        let _ = stderr.write_all(buf.as_bytes());
+        let _ = stderr.flush();
+    }
+}
@@ -1,20 +1,72 @@
 use crate::{
     Error,
-    args::FfmpegArgs,
-    progress::{ProgressBar, ProgressStats},
+    args::{self, DEFAULT_FFMPEG, FfmpegArgs},
+    dry_run, hooks,
+    keys::{KeyReader, RawMode},
+    log::LogFile,
+    pause,
+    progress::{
+        Overall, ProgressBar, ProgressStats, Stage, WARN_COLOR, dim, fg, format_time, reset,
+    },
+    region::Region,
+    status,
+    stderr::{
+        Level, StderrBuffer, StderrRenderer, is_prompt, is_transient, line_level, mapped_output,
+    },
+    transport::{ProgressSource, Transport},
+    two_pass, verify,
+    webhook::Webhook,
 };
 use std::{
+    env,
     io::{self, BufRead, BufReader, Read, Write},
-    process::{Command, Stdio},
+    path::{Path, PathBuf},
+    process::{ChildStdin, Command, Stdio},
     sync::{
         Arc, Mutex,
         atomic::{AtomicBool, Ordering},
+        mpsc::{self, RecvTimeoutError},
     },
     thread,
+    time::{Duration, Instant},
 };
 
 static INTERRUPTED: AtomicBool = AtomicBool::new(false);
 
+enum Event {
+    /// A `key=value` line of the `-progress` output.
+    Progress(String),
+    /// A warning or error from stderr to show above the bar.
+    Stderr(String),
+    /// A key pressed while ffpb owns the terminal.
+    Key(u8),
+    /// ffmpeg closed its stdout, no more progress will follow.
+    ProgressClosed,
+}
+
+/// How often the bar is refreshed and the watchdog checked while ffmpeg is silent.
+const TICK: Duration = Duration::from_millis(250);
+
+/// Wait before the first retry, doubled for every further one.
+const RETRY_BACKOFF: Duration = Duration::from_secs(2);
+const RETRY_BACKOFF_MAX: Duration = Duration::from_secs(60);
+
+/// Outcome of one run of ffmpeg.
+#[derive(Default)]
+pub struct Attempt {
+    pub code: i32,
+    /// First stderr line classified as a transient failure.
+    pub transient: Option<String>,
+    /// Effective duration computed from the input duration and time options.
+    pub expected_us: Option<u64>,
+    /// Number of streams mapped to each output file, by output index.
+    pub mapped_streams: Vec<usize>,
+    /// Last reported position in the output.
+    pub out_time_us: u64,
+    /// Last reported size of the output.
+    pub total_size: u64,
+}
+
 fn parse_duration_line(line: &str) -> Option<f64> {
     // Example: "  Duration: 00:01:30.50, start: 0.000000, bitrate: 2450 kb/s"
     let marker = "Duration: ";
@@ -29,6 +81,13 @@ fn parse_duration_line(line: &str) -> Option<f64> {
     crate::args::parse_time(time_str)
 }
 
+fn parse_stream_q_key(key: &str) -> Option<(usize, usize)> {
+    // Example: "stream_1_0_q" -> (1, 0)
+    let rest = key.strip_prefix("stream_")?.strip_suffix("_q")?;
+    let (file, stream) = rest.split_once('_')?;
+    Some((file.parse().ok()?, stream.parse().ok()?))
+}
+
 fn apply_progress_kv(stats: &mut ProgressStats, key: &str, value: &str) {
     match key {
         "frame" => {
@@ -37,14 +96,11 @@ fn apply_progress_kv(stats: &mut ProgressStats, key: &str, value: &str) {
         "fps" => {
             stats.fps = value.parse().unwrap_or(0.0);
         }
-        "stream_0_0_q" => {
-            stats.q = value.parse().unwrap_or(0.0);
-        }
         "bitrate" => {
             // e.g. "2450.3kbits/s" or "N/A"
-            if let Some(kbits_str) = value.strip_suffix("kbits/s") {
-                stats.bitrate_kbps = kbits_str.trim().parse().unwrap_or(0.0);
-            }
+            stats.bitrate_kbps = value
+                .strip_suffix("kbits/s")
+                .and_then(|kbits_str| kbits_str.trim().parse().ok());
         }
         "total_size" => {
             stats.total_size = value.parse().unwrap_or(0);
@@ -52,6 +108,19 @@ fn apply_progress_kv(stats: &mut ProgressStats, key: &str, value: &str) {
         "out_time_us" => {
             stats.out_time_us = value.parse().unwrap_or(0);
         }
+        "out_time_ms" => {
+            // Despite the name, ffmpeg reports this in microseconds as well.
+            stats.out_time_ms = value.parse().unwrap_or(0);
+        }
+        "out_time" => {
+            stats.out_time = value.to_string();
+        }
+        "dup_frames" => {
+            stats.dup_frames = value.parse().unwrap_or(0);
+        }
+        "drop_frames" => {
+            stats.drop_frames = value.parse().unwrap_or(0);
+        }
         "speed" => {
             // e.g. "1.82x" or "N/A"
             if let Some(speed_str) = value.strip_suffix('x') {
@@ -61,10 +130,28 @@ fn apply_progress_kv(stats: &mut ProgressStats, key: &str, value: &str) {
         "progress" => {
             stats.is_end = value == "end";
         }
-        _ => {}
+        _ => {
+            if let Some(index) = parse_stream_q_key(key) {
+                let q = value.parse().unwrap_or(0.0);
+                if index == (0, 0) {
+                    stats.q = q;
+                }
+                stats.stream_q.insert(index, q);
+            } else {
+                // Keep keys introduced by newer ffmpeg versions around.
+                stats.extra.insert(key.to_string(), value.to_string());
+            }
+        }
     }
 }
 
+/// The arguments ffmpeg runs with, reporting its progress to `progress_url`.
+pub fn command_args(args: &FfmpegArgs, progress_url: &str) -> Vec<String> {
+    let mut command = args.args.clone();
+    command.extend(["-progress".to_string(), progress_url.to_string()]);
+    command
+}
+
 /// Compute the effective output duration in microseconds.
 pub fn compute_effective_duration(
     args: &FfmpegArgs,
@@ -92,122 +179,757 @@ pub fn compute_effective_duration(
     effective.map(|secs| (secs * 1_000_000.0) as u64)
 }
 
-pub fn run_ffmpeg(args: &FfmpegArgs) -> Result<i32, Error> {
+/// First line of `<program> -version`, e.g. "ffmpeg version 7.1 Copyright ...".
+pub fn program_version(program: &str) -> Option<String> {
+    let output = Command::new(program)
+        .arg("-version")
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .output()
+        .ok()?;
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    stdout.lines().next().map(|line| line.trim().to_string())
+}
+
+/// Where a program given by name or path is found, the way `Command` finds it.
+pub fn resolve_program(program: &str) -> Option<PathBuf> {
+    let path = Path::new(program);
+    if path.components().count() > 1 {
+        return path.is_file().then(|| path.to_path_buf());
+    }
+    env::split_paths(&env::var_os("PATH")?)
+        .map(|dir| dir.join(format!("{program}{}", env::consts::EXE_SUFFIX)))
+        .find(|candidate| candidate.is_file())
+}
+
+/// Print which ffmpeg (and ffprobe, with `--verify`) will run, once per process.
+fn report_programs(args: &FfmpegArgs, region: &Option<Region>) {
+    static REPORTED: AtomicBool = AtomicBool::new(false);
+    if !args.verbose || REPORTED.swap(true, Ordering::SeqCst) {
+        return;
+    }
+
+    let mut programs = vec![args.ffmpeg.clone()];
+    if args.verify {
+        programs.push(args.ffprobe());
+    }
+    for program in programs {
+        let line = match resolve_program(&program) {
+            Some(path) => format!(
+                "using {} ({})",
+                path.display(),
+                program_version(&program).unwrap_or_else(|| "version unknown".to_string())
+            ),
+            None => format!("{program} not found"),
+        };
+        let mut buf = String::new();
+        if args.color.enabled() {
+            dim(&mut buf);
+        }
+        buf.push_str(&line);
+        if args.color.enabled() {
+            reset(&mut buf);
+        }
+        print_line(region, &buf);
+    }
+}
+
+/// Keyboard controls need the terminal to themselves, so they are off when
+/// ffmpeg reads media or nothing at all from stdin.
+fn keys_allowed(args: &FfmpegArgs) -> bool {
+    if args.no_keys || args.args.iter().any(|a| a == "-nostdin") {
+        return false;
+    }
+    !args
+        .args
+        .windows(2)
+        .any(|w| w[0] == "-i" && matches!(w[1].as_str(), "-" | "pipe:" | "pipe:0"))
+}
+
+fn key_hint(command_input: Option<&str>) -> String {
+    match command_input {
+        Some(input) => format!("command (target time command arg, esc to cancel): {input}_"),
+        None => "p pause • q quit • l log • s stats • c command".to_string(),
+    }
+}
+
+fn send_to_ffmpeg(stdin: &mut Option<ChildStdin>, bytes: &[u8]) {
+    if let Some(stdin) = stdin {
+        let _ = stdin.write_all(bytes);
+        let _ = stdin.flush();
+    }
+}
+
+/// Echo a key typed at one of ffmpeg's prompts and pass it on.
+fn forward_key(stdin: &mut Option<ChildStdin>, key: u8) {
+    let mut stderr = io::stderr().lock();
+    match key {
+        b'\r' | b'\n' => {
+            let _ = writeln!(stderr);
+            send_to_ffmpeg(stdin, b"\n");
+        }
+        0x7f | 0x08 => {
+            let _ = write!(stderr, "\x08 \x08");
+            send_to_ffmpeg(stdin, &[0x08]);
+        }
+        _ => {
+            let _ = stderr.write_all(&[key]);
+            send_to_ffmpeg(stdin, &[key]);
+        }
+    }
+    let _ = stderr.flush();
+}
+
+fn log_line(log: &Option<Arc<Mutex<LogFile>>>, line: &str) {
+    if let Some(log) = log
+        && let Ok(mut log) = log.lock()
+    {
+        log.line(line);
+    }
+}
+
+/// Print a line above the bars of `region`, or straight to stderr.
+pub fn print_line(region: &Option<Region>, line: &str) {
+    match region {
+        Some(region) => region.print_above(line),
+        None => eprintln!("{line}"),
+    }
+}
+
+/// Print a warning of ffpb itself, above the bars if there are any.
+fn warn(color: bool, region: &Option<Region>, message: &str) {
+    let mut line = String::new();
+    if color {
+        fg(&mut line, WARN_COLOR.0, WARN_COLOR.1, WARN_COLOR.2);
+    }
+    line.push_str(message);
+    if color {
+        reset(&mut line);
+    }
+    print_line(region, &line);
+}
+
+/// Whether the user pressed Ctrl-C during a run.
+pub fn interrupted() -> bool {
+    INTERRUPTED.load(Ordering::SeqCst)
+}
+
+/// Called with the latest stats and, when the duration is known, the
+/// completed fraction of the run on every progress update.
+pub type ProgressCallback = Arc<dyn Fn(&ProgressStats, Option<f64>) + Send + Sync>;
+
+/// Called with every line ffmpeg writes to stderr, whether it is shown or not.
+pub type StderrCallback = Arc<dyn Fn(&str) + Send + Sync>;
+
+/// How a single ffmpeg run is presented when it is part of a larger session.
+#[derive(Clone, Default)]
+pub struct RunContext {
+    /// Progress of the batch this run belongs to.
+    pub overall: Option<Overall>,
+    /// Shown in front of the bar to tell several bars apart.
+    pub label: Option<String>,
+    /// Draw the bar, and print ffmpeg's output, in a region shared with the
+    /// bars of other runs.
+    pub region: Option<Region>,
+    pub on_progress: Option<ProgressCallback>,
+    pub on_stderr: Option<StderrCallback>,
+    /// Part of the bar this run fills when it is one of several runs.
+    pub stage: Option<Stage>,
+}
+
+impl RunContext {
+    /// Call `f` on every progress update, after any callback already set.
+    fn observe_progress(
+        &mut self,
+        f: impl Fn(&ProgressStats, Option<f64>) + Send + Sync + 'static,
+    ) {
+        let previous = self.on_progress.take();
+        self.on_progress = Some(Arc::new(move |stats, fraction| {
+            if let Some(ref previous) = previous {
+                previous(stats, fraction);
+            }
+            f(stats, fraction);
+        }));
+    }
+
+    /// Call `f` on every stderr line, after any callback already set.
+    fn observe_stderr(&mut self, f: impl Fn(&str) + Send + Sync + 'static) {
+        let previous = self.on_stderr.take();
+        self.on_stderr = Some(Arc::new(move |line| {
+            if let Some(ref previous) = previous {
+                previous(line);
+            }
+            f(line);
+        }));
+    }
+}
+
+pub fn run_ffmpeg(args: &FfmpegArgs, ctx: &RunContext) -> Result<i32, Error> {
+    if args.dry_run {
+        dry_run::print(args)?;
+        return Ok(0);
+    }
+
+    let started_at = Instant::now();
+    let mut ctx = ctx.clone();
+    report_programs(args, &ctx.region);
+
+    let webhook = match args.webhook {
+        Some(ref url) => Some(Arc::new(Webhook::start(url, args.webhook_every, args)?)),
+        None => None,
+    };
+    if let Some(ref webhook) = webhook {
+        webhook.started();
+        let webhook = Arc::clone(webhook);
+        ctx.observe_progress(move |stats, fraction| webhook.progress(stats, fraction));
+    }
+
+    let monitor = match args.listen {
+        Some(addr) => Some(Arc::new(status::register(addr, args)?)),
+        None => None,
+    };
+    if let Some(ref monitor) = monitor {
+        let progress = Arc::clone(monitor);
+        ctx.observe_progress(move |stats, fraction| progress.progress(stats, fraction));
+        let stderr = Arc::clone(monitor);
+        ctx.observe_stderr(move |line| stderr.log(line));
+    }
+
+    let result = run_and_verify(args, &ctx);
+
+    if let Some(monitor) = monitor {
+        monitor.finish(&result);
+    }
+    if let Some(webhook) = webhook
+        && let Some(warning) = webhook.finish(&result, started_at.elapsed())
+    {
+        print_line(&ctx.region, &warning);
+    }
+    hooks::run_hooks(args, &result, started_at.elapsed(), &ctx.region);
+    result.map(|attempt| attempt.code)
+}
+
+fn run_and_verify(args: &FfmpegArgs, ctx: &RunContext) -> Result<Attempt, Error> {
+    let attempt = if args.two_pass {
+        two_pass::run(args, ctx)?
+    } else {
+        run_with_retries(args, ctx)?
+    };
+
+    if args.verify && attempt.code == 0 {
+        let verified = verify::verify_outputs(
+            &args.ffprobe(),
+            &args.outputs,
+            attempt.expected_us,
+            &attempt.mapped_streams,
+        )?;
+        for line in verified {
+            print_line(&ctx.region, &line);
+        }
+    }
+    Ok(attempt)
+}
+
+/// Run ffmpeg, and run it again with a growing delay when it fails with a
+/// transient error and `--retries` allows it.
+pub fn run_with_retries(args: &FfmpegArgs, ctx: &RunContext) -> Result<Attempt, Error> {
+    let attempts = args.retries + 1;
+    let mut attempt_args = args.clone();
+    let mut attempt = 1;
+
+    loop {
+        let result = run_attempt(&attempt_args, ctx, attempt)?;
+        let Some(reason) = result.transient.clone() else {
+            return Ok(result);
+        };
+        if result.code == 0 || result.code == 130 || attempt >= attempts || interrupted() {
+            return Ok(result);
+        }
+
+        let delay = RETRY_BACKOFF
+            .saturating_mul(1 << (attempt - 1).min(16))
+            .min(RETRY_BACKOFF_MAX);
+        warn(
+            args.color.enabled(),
+            &ctx.region,
+            &format!(
+                "transient failure ({}), retrying in {} (attempt {}/{attempts})",
+                reason.trim(),
+                format_time(delay.as_micros() as u64),
+                attempt + 1
+            ),
+        );
+
+        let waiting_since = Instant::now();
+        while waiting_since.elapsed() < delay {
+            if interrupted() {
+                return Ok(Attempt {
+                    code: 130,
+                    ..result
+                });
+            }
+            thread::sleep(TICK);
+        }
+
+        // The failed attempt may have left a partial output behind.
+        if !attempt_args.args.iter().any(|a| a == "-y" || a == "-n") {
+            attempt_args.args.insert(0, "-y".to_string());
+        }
+        attempt += 1;
+    }
+}
+
+fn run_attempt(args: &FfmpegArgs, ctx: &RunContext, attempt: u32) -> Result<Attempt, Error> {
     let _ = ctrlc::set_handler(|| {
         INTERRUPTED.store(true, Ordering::SeqCst);
     });
+    pause::install_signal_handler();
 
-    let mut child = Command::new("ffmpeg")
-        .args(&args.args)
-        .stdin(Stdio::inherit())
-        .stdout(Stdio::piped())
-        .stderr(Stdio::piped())
-        .spawn()
-        .map_err(|e| {
-            if e.kind() == io::ErrorKind::NotFound {
-                Error::FfmpegNotFound
-            } else {
-                Error::SpawnFailed(e)
+    let log = match &args.log_file {
+        Some(path) => {
+            let log = LogFile::open(path, args.log_max_size).map_err(Error::LogFailed)?;
+            Some(Arc::new(Mutex::new(log)))
+        }
+        None => None,
+    };
+    let source = ProgressSource::open(Transport::for_args(args)).map_err(Error::SpawnFailed)?;
+    let ffmpeg_args = command_args(args, source.url());
+
+    if log.is_some() {
+        log_line(&log, &format!("ffpb {}", env!("CARGO_PKG_VERSION")));
+        log_line(
+            &log,
+            &format!("command: {}", args::shell_join(&args.ffmpeg, &ffmpeg_args)),
+        );
+        log_line(
+            &log,
+            &program_version(&args.ffmpeg).unwrap_or_else(|| "ffmpeg version unknown".to_string()),
+        );
+        if args.retries > 0 {
+            log_line(&log, &format!("attempt {attempt}/{}", args.retries + 1));
+        }
+    }
+
+    // With keyboard controls ffpb owns the terminal and talks to ffmpeg's stdin.
+    let raw_mode = if keys_allowed(args) {
+        RawMode::enable()
+    } else {
+        None
+    };
+
+    let mut tee = match args.progress_target {
+        Some(ref target) => match target.open() {
+            Ok(tee) => Some(tee),
+            Err(e) => {
+                warn(
+                    args.color.enabled(),
+                    &ctx.region,
+                    &format!("failed to open the -progress target ({e})"),
+                );
+                None
             }
-        })?;
+        },
+        None => None,
+    };
+
+    let mut command = Command::new(&args.ffmpeg);
+    command
+        .args(&ffmpeg_args)
+        .stdin(if raw_mode.is_some() {
+            Stdio::piped()
+        } else {
+            Stdio::inherit()
+        })
+        .stderr(Stdio::piped());
+    source.configure(&mut command);
+    let mut child = command.spawn().map_err(|e| {
+        if e.kind() == io::ErrorKind::NotFound && args.ffmpeg == DEFAULT_FFMPEG {
+            Error::FfmpegNotFound
+        } else {
+            Error::SpawnFailed(e)
+        }
+    })?;
+    let (connection, link) = source.start(&mut child);
 
     // Shared storage for the duration parsed from stderr
     let duration_secs = Arc::new(Mutex::new(None));
 
     // In clean mode: discard stderr output entirely.
-    // In default mode: forward stderr before encoding starts, suppress during encoding.
+    // In default mode: forward stderr before encoding starts. While encoding,
+    // pass warnings and errors through above the bar and keep the rest in a
+    // bounded buffer which is printed once ffmpeg is done.
+    // The full log view (`l` key) passes everything through in both modes.
     let clean_mode = args.clean;
     let encoding_active = Arc::new(AtomicBool::new(false));
-    let stderr_buffer = Arc::new(Mutex::new(Vec::<u8>::new()));
+    let show_log = Arc::new(AtomicBool::new(false));
+    let stderr_buffer = Arc::new(Mutex::new(StderrBuffer::default()));
+    let transient = Arc::new(Mutex::new(None));
+    let mapped_streams = Arc::new(Mutex::new(Vec::new()));
+
+    // Progress lines and passed-through stderr lines both arrive here, so the
+    // loop below keeps ticking (and can notice a stall) while ffmpeg is silent.
+    let (tx, rx) = mpsc::channel();
 
     let mut stderr = child.stderr.take().expect("stderr should be piped");
     let duration_clone = Arc::clone(&duration_secs);
     let encoding_clone = Arc::clone(&encoding_active);
+    let show_log_clone = Arc::clone(&show_log);
     let buffer_clone = Arc::clone(&stderr_buffer);
+    let log_clone = log.clone();
+    let transient_clone = Arc::clone(&transient);
+    let on_stderr = ctx.on_stderr.clone();
+    let mapped_clone = Arc::clone(&mapped_streams);
+    let stderr_tx = tx.clone();
+    let key_tx = tx.clone();
+    let region_clone = ctx.region.clone();
+    let mut renderer = StderrRenderer::new(args.color.enabled(), args.pretty);
     let stderr_handle = thread::spawn(move || {
         let real_stderr = io::stderr();
         let mut found_duration = false;
-        let mut line_buf = String::new();
+        let mut in_mapping = false;
+        let mut line_buf = Vec::new();
         let mut buf = [0u8; 256];
 
         loop {
-            match stderr.read(&mut buf) {
+            let n = match stderr.read(&mut buf) {
                 Ok(0) | Err(_) => break,
-                Ok(n) => {
-                    let chunk = &buf[..n];
+                Ok(n) => n,
+            };
+            line_buf.extend_from_slice(&buf[..n]);
 
-                    if !clean_mode {
-                        if encoding_clone.load(Ordering::SeqCst) {
-                            if let Ok(mut buffer) = buffer_clone.lock() {
-                                buffer.extend_from_slice(chunk);
-                            }
-                        } else {
-                            let mut stderr = real_stderr.lock();
-                            let _ = stderr.write_all(chunk);
-                            let _ = stderr.flush();
-                        }
+            while let Some(pos) = line_buf.iter().position(|b| *b == b'\n') {
+                let raw = line_buf.drain(..=pos).collect::<Vec<_>>();
+                let line = String::from_utf8_lossy(&raw[..pos])
+                    .trim_end_matches('\r')
+                    .to_string();
+
+                log_line(&log_clone, &line);
+                if let Some(ref on_stderr) = on_stderr {
+                    on_stderr(&line);
+                }
+
+                if is_transient(&line)
+                    && let Ok(mut transient) = transient_clone.lock()
+                    && transient.is_none()
+                {
+                    *transient = Some(line.clone());
+                }
+
+                if line.starts_with("Stream mapping:") {
+                    in_mapping = true;
+                } else if !line.starts_with(' ') {
+                    in_mapping = false;
+                } else if in_mapping
+                    && let Some(file) = mapped_output(&line)
+                    && let Ok(mut mapped) = mapped_clone.lock()
+                {
+                    if mapped.len() <= file {
+                        mapped.resize(file + 1, 0);
                     }
+                    mapped[file] += 1;
+                }
 
-                    // Parse duration internally
-                    if !found_duration && let Ok(s) = std::str::from_utf8(chunk) {
-                        line_buf.push_str(s);
-                        while let Some(pos) = line_buf.find('\n') {
-                            let line = line_buf[..pos].to_string();
-                            line_buf.drain(..=pos);
-                            if let Some(dur) = parse_duration_line(&line) {
-                                if let Ok(mut lock) = duration_clone.lock() {
-                                    *lock = Some(dur);
-                                }
-                                found_duration = true;
-                                break;
-                            }
-                        }
+                // Parse duration internally
+                if !found_duration && let Some(dur) = parse_duration_line(&line) {
+                    if let Ok(mut lock) = duration_clone.lock() {
+                        *lock = Some(dur);
                     }
+                    found_duration = true;
                 }
+
+                let show_log = show_log_clone.load(Ordering::SeqCst);
+                if clean_mode && !show_log {
+                    continue;
+                }
+
+                if !encoding_clone.load(Ordering::SeqCst) {
+                    for rendered in renderer.line(&line) {
+                        print_line(&region_clone, &rendered);
+                    }
+                } else if show_log || line_level(&line) >= Level::Warning {
+                    for rendered in renderer.line(&line) {
+                        let _ = stderr_tx.send(Event::Stderr(rendered));
+                    }
+                } else if let Ok(mut buffer) = buffer_clone.lock() {
+                    buffer.push(line);
+                }
+            }
+
+            // ffmpeg waits for an answer on prompts like "Overwrite? [y/N] "
+            // without ending the line, so show those right away, even in
+            // clean mode. The answer goes straight to ffmpeg's inherited stdin.
+            if is_prompt(&line_buf) {
+                let line = String::from_utf8_lossy(&line_buf).to_string();
+                log_line(&log_clone, &line);
+                let mut stderr = real_stderr.lock();
+                if !clean_mode {
+                    for rendered in renderer.finish() {
+                        let _ = writeln!(stderr, "{rendered}");
+                    }
+                }
+                let _ = write!(stderr, "{}", renderer.prompt(&line));
+                let _ = stderr.flush();
+                line_buf.clear();
+            }
+        }
+
+        if !line_buf.is_empty() {
+            let line = String::from_utf8_lossy(&line_buf).to_string();
+            log_line(&log_clone, &line);
+            if !clean_mode
+                && encoding_clone.load(Ordering::SeqCst)
+                && let Ok(mut buffer) = buffer_clone.lock()
+            {
+                buffer.push(line);
+            }
+        }
+        for rendered in renderer.finish() {
+            let _ = stderr_tx.send(Event::Stderr(rendered));
+        }
+    });
+
+    let color = args.color.enabled();
+    let warn_region = ctx.region.clone();
+    thread::spawn(move || {
+        let Some(progress) = connection.wait() else {
+            let _ = tx.send(Event::ProgressClosed);
+            return;
+        };
+        for line in BufReader::new(progress).lines() {
+            let Ok(line) = line else { break };
+            if let Some(ref mut writer) = tee
+                && let Err(e) = writer.line(&line)
+            {
+                warn(
+                    color,
+                    &warn_region,
+                    &format!("stopped copying progress to the -progress target ({e})"),
+                );
+                tee = None;
+            }
+            if tx.send(Event::Progress(line)).is_err() {
+                break;
             }
         }
+        let _ = tx.send(Event::ProgressClosed);
     });
 
-    // Read progress from stdout
-    let stdout = child.stdout.take().expect("stdout should be piped");
-    let reader = BufReader::new(stdout);
+    let mut ffmpeg_stdin = child.stdin.take();
+    let _key_reader = raw_mode
+        .is_some()
+        .then(|| KeyReader::spawn(move |key| key_tx.send(Event::Key(key)).is_ok()));
+    let mut command_input: Option<String> = None;
 
     let mut progress_bar: Option<ProgressBar> = None;
     let mut stats = ProgressStats::default();
     let mut bar_initialized = false;
 
-    for line in reader.lines() {
+    let started_at = Instant::now();
+    let mut last_advance = Instant::now();
+    let mut killed_by = None;
+    let mut paused = false;
+
+    loop {
         if INTERRUPTED.load(Ordering::SeqCst) {
             if let Some(ref mut bar) = progress_bar {
                 bar.interrupt();
             }
+            if paused {
+                // A stopped ffmpeg can't handle the interrupt and would never exit.
+                pause::set_suspended(&child, false);
+            }
             let _ = child.wait();
-            return Ok(130);
+            link.abandon();
+            log_line(&log, "interrupted by user");
+            return Ok(Attempt {
+                code: 130,
+                ..Attempt::default()
+            });
+        }
+
+        if let Some(timeout) = args.timeout
+            && started_at.elapsed() >= timeout
+        {
+            killed_by = Some(Error::TimedOut(timeout));
+            break;
+        }
+
+        if pause::requested() != paused && pause::set_suspended(&child, !paused) {
+            paused = !paused;
+            log_line(&log, if paused { "paused" } else { "resumed" });
+            if let Some(ref mut bar) = progress_bar {
+                bar.set_paused(paused);
+                bar.set_stalled(None);
+                bar.update(&stats, true);
+            }
+        }
+
+        // A paused ffmpeg is not stalled.
+        if paused {
+            last_advance = Instant::now();
         }
 
-        let Ok(line) = line else { break };
+        let stalled_for = last_advance.elapsed();
+        if let Some(stall_timeout) = args.stall_timeout
+            && stalled_for >= stall_timeout
+        {
+            killed_by = Some(Error::Stalled(stalled_for));
+            break;
+        }
+
+        let line = match rx.recv_timeout(TICK) {
+            Ok(Event::Progress(line)) => line,
+            Ok(Event::Stderr(line)) => {
+                match progress_bar {
+                    Some(ref mut bar) => bar.print_above(&line),
+                    None => print_line(&ctx.region, &line),
+                }
+                continue;
+            }
+            Ok(Event::Key(key)) => {
+                let Some(ref mut bar) = progress_bar else {
+                    // Before encoding starts keys are answers to ffmpeg's
+                    // prompts, so echo and forward them.
+                    forward_key(&mut ffmpeg_stdin, key);
+                    continue;
+                };
+
+                if let Some(ref mut input) = command_input {
+                    match key {
+                        b'\r' | b'\n' => {
+                            // ffmpeg reads a filter command line after `c`.
+                            send_to_ffmpeg(&mut ffmpeg_stdin, format!("c{input}\n").as_bytes());
+                            log_line(&log, &format!("sent command: {input}"));
+                            command_input = None;
+                        }
+                        0x1b => command_input = None,
+                        0x7f | 0x08 => {
+                            input.pop();
+                        }
+                        key if key.is_ascii_graphic() || key == b' ' => input.push(key as char),
+                        _ => {}
+                    }
+                } else {
+                    match key {
+                        b'p' => pause::request_toggle(),
+                        b'q' => {
+                            send_to_ffmpeg(&mut ffmpeg_stdin, b"q");
+                            bar.set_footer(Some("quitting...".to_string()));
+                            bar.update(&stats, true);
+                            continue;
+                        }
+                        b'l' => {
+                            let enabled = !show_log.load(Ordering::SeqCst);
+                            show_log.store(enabled, Ordering::SeqCst);
+                            if enabled && let Ok(mut buffer) = stderr_buffer.lock() {
+                                let mut renderer = StderrRenderer::new(args.color.enabled(), false);
+                                for line in buffer.lines() {
+                                    for rendered in renderer.line(line) {
+                                        bar.print_above(&rendered);
+                                    }
+                                }
+                                for rendered in renderer.finish() {
+                                    bar.print_above(&rendered);
+                                }
+                                *buffer = StderrBuffer::default();
+                            }
+                        }
+                        b's' => bar.toggle_stats(),
+                        b'c' => command_input = Some(String::new()),
+                        _ => {}
+                    }
+                }
+
+                bar.set_footer(Some(key_hint(command_input.as_deref())));
+                bar.update(&stats, true);
+                continue;
+            }
+            Err(RecvTimeoutError::Timeout) => {
+                // ffmpeg may exit without ever connecting to a listener.
+                if !link.connected() && matches!(child.try_wait(), Ok(Some(_))) {
+                    break;
+                }
+                if let Some(ref mut bar) = progress_bar {
+                    if stalled_for >= args.stall_after {
+                        bar.set_stalled(Some(stalled_for));
+                    }
+                    bar.update(&stats, false);
+                }
+                continue;
+            }
+            Ok(Event::ProgressClosed) | Err(RecvTimeoutError::Disconnected) => break,
+        };
         let line = line.trim();
 
         if let Some((key, value)) = line.split_once('=') {
-            apply_progress_kv(&mut stats, key.trim(), value.trim());
+            let (key, value) = (key.trim(), value.trim());
+
+            match key {
+                "out_time_us" if value.parse().unwrap_or(0) != stats.out_time_us => {
+                    last_advance = Instant::now();
+                }
+                "total_size" if value.parse().unwrap_or(0) != stats.total_size => {
+                    last_advance = Instant::now();
+                }
+                _ => {}
+            }
 
-            if key.trim() == "progress" {
+            apply_progress_kv(&mut stats, key, value);
+
+            if key == "progress" {
                 if !bar_initialized {
                     encoding_active.store(true, Ordering::SeqCst);
                     let total_dur = duration_secs.lock().ok().and_then(|d| *d);
                     let effective_us = compute_effective_duration(args, total_dur);
-                    progress_bar = Some(ProgressBar::new(effective_us, clean_mode));
+                    let mut bar = match &ctx.region {
+                        Some(region) => ProgressBar::in_region(effective_us, clean_mode, region),
+                        None => ProgressBar::new(effective_us, clean_mode),
+                    };
+                    bar.set_refresh(args.refresh);
+                    bar.set_label(ctx.label.clone());
+                    bar.set_attempt((attempt > 1).then_some((attempt, args.retries + 1)));
+                    bar.set_stage(ctx.stage.clone());
+                    bar.set_paused(paused);
+                    if args.per_output && args.outputs.len() > 1 {
+                        bar.show_outputs(args.outputs.clone());
+                    }
+                    if raw_mode.is_some() {
+                        bar.set_footer(Some(key_hint(None)));
+                    }
+                    bar.set_overall(ctx.overall);
+                    progress_bar = Some(bar);
                     bar_initialized = true;
                 }
 
+                let effective_us = duration_secs
+                    .lock()
+                    .ok()
+                    .and_then(|d| *d)
+                    .and_then(|dur| compute_effective_duration(args, Some(dur)));
+
+                if let Some(ref on_progress) = ctx.on_progress {
+                    let fraction = match effective_us {
+                        _ if stats.is_end => Some(1.0),
+                        Some(total) if total > 0 => {
+                            Some((stats.out_time_us as f64 / total as f64).min(1.0))
+                        }
+                        _ => None,
+                    };
+                    let fraction = match ctx.stage {
+                        Some(ref stage) => fraction.map(|f| stage.fraction(f)),
+                        None => fraction,
+                    };
+                    on_progress(&stats, fraction);
+                }
+
                 if let Some(ref mut bar) = progress_bar {
-                    if let Ok(lock) = duration_secs.lock()
-                        && let Some(dur) = *lock
-                        && let Some(eff) = compute_effective_duration(args, Some(dur))
-                    {
+                    if let Some(eff) = effective_us {
                         bar.set_total_duration(eff);
                     }
 
+                    let stalled_for = last_advance.elapsed();
+                    bar.set_stalled((stalled_for >= args.stall_after).then_some(stalled_for));
+
                     if stats.is_end {
                         bar.finish(&stats);
                     } else {
@@ -224,22 +946,83 @@ pub fn run_ffmpeg(args: &FfmpegArgs) -> Result<i32, Error> {
         }
     }
 
+    link.abandon();
+    if killed_by.is_some() {
+        if let Some(ref mut bar) = progress_bar {
+            bar.interrupt();
+        }
+        let _ = child.kill();
+    }
+
     // Wait for stderr thread
     let _ = stderr_handle.join();
 
+    // Warnings and errors which arrived after the bar was finished
+    for event in rx.try_iter() {
+        if let Event::Stderr(line) = event {
+            print_line(&ctx.region, &line);
+        }
+    }
+
     // Flush buffered stderr from encoding phase
     if !clean_mode
         && let Ok(buffer) = stderr_buffer.lock()
         && !buffer.is_empty()
     {
-        let stderr = io::stderr();
-        let mut lock = stderr.lock();
-        let _ = lock.write_all(&buffer);
-        let _ = lock.flush();
+        if buffer.omitted() > 0 {
+            print_line(
+                &ctx.region,
+                &format!(
+                    "\x1b[2m... {} earlier lines omitted\x1b[0m",
+                    buffer.omitted()
+                ),
+            );
+        }
+        let mut renderer = StderrRenderer::new(args.color.enabled(), false);
+        for line in buffer.lines() {
+            for rendered in renderer.line(line) {
+                print_line(&ctx.region, &rendered);
+            }
+        }
+        for rendered in renderer.finish() {
+            print_line(&ctx.region, &rendered);
+        }
     }
 
     // Wait for ffmpeg to exit
     let status = child.wait().map_err(Error::SpawnFailed)?;
 
-    Ok(status.code().unwrap_or(1))
+    if let Some(e) = killed_by {
+        log_line(&log, &format!("terminated: {e}"));
+        return Err(e);
+    }
+
+    match status.code() {
+        Some(code) => log_line(&log, &format!("ffmpeg exited with status {code}")),
+        None => log_line(&log, "ffmpeg was terminated by a signal"),
+    }
+
+    let code = status.code().unwrap_or(1);
+    let transient = transient.lock().ok().and_then(|t| t.clone());
+    if code != 0
+        && let Some(ref reason) = transient
+    {
+        log_line(&log, &format!("transient failure: {reason}"));
+    }
+
+    let expected_us = duration_secs
+        .lock()
+        .ok()
+        .and_then(|d| *d)
+        .and_then(|dur| compute_effective_duration(args, Some(dur)));
+    let mapped_streams = mapped_streams.lock().map(|m| m.clone()).unwrap_or_default();
+
+    Ok(Attempt {
+        code,
+        transient,
+        expected_us,
+        mapped_streams,
+        out_time_us: stats.out_time_us,
+        total_size: stats.total_size,
+    })
 }
@@ -0,0 +1,530 @@
+use crate::{
+    Error,
+    args::FfmpegArgs,
+    http,
+    json::{self, Object},
+    progress::{ProgressStats, format_size, format_time_clock},
+    runner::Attempt,
+};
+use std::{
+    collections::VecDeque,
+    fmt::Write,
+    net::{SocketAddr, TcpListener, TcpStream},
+    process,
+    sync::{Arc, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+
+/// Number of stderr lines kept per run.
+const LOG_LINES: usize = 20;
+/// How long a client may take to send its request.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+/// Refresh interval of the html page in seconds.
+const PAGE_REFRESH: u32 = 2;
+
+/// The status server of this process, started by the first run that asks
+/// for it. Jobs of a batch share it and each show up as a run.
+static SERVER: Mutex<Option<Arc<Server>>> = Mutex::new(None);
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum State {
+    Starting,
+    Encoding,
+    Finished,
+    Failed,
+}
+
+impl State {
+    fn name(self) -> &'static str {
+        match self {
+            State::Starting => "starting",
+            State::Encoding => "encoding",
+            State::Finished => "finished",
+            State::Failed => "failed",
+        }
+    }
+}
+
+struct Run {
+    state: State,
+    inputs: Vec<String>,
+    outputs: Vec<String>,
+    started_at: Instant,
+    /// Set once the run finished, so elapsed time stops counting.
+    elapsed: Option<Duration>,
+    stats: ProgressStats,
+    /// Counts of earlier attempts and passes, which start again from zero.
+    offsets: Offsets,
+    fraction: Option<f64>,
+    exit_code: Option<i32>,
+    error: Option<String>,
+    log: VecDeque<String>,
+}
+
+/// What the counters of a run reached before ffmpeg started over, so the
+/// `_total` metrics never go down.
+#[derive(Default)]
+struct Offsets {
+    frames: u64,
+    dup_frames: u64,
+    drop_frames: u64,
+}
+
+impl Run {
+    fn frames(&self) -> u64 {
+        self.offsets.frames + self.stats.frame
+    }
+
+    fn dup_frames(&self) -> u64 {
+        self.offsets.dup_frames + self.stats.dup_frames
+    }
+
+    fn drop_frames(&self) -> u64 {
+        self.offsets.drop_frames + self.stats.drop_frames
+    }
+
+    fn elapsed(&self) -> Duration {
+        self.elapsed.unwrap_or_else(|| self.started_at.elapsed())
+    }
+
+    /// Seconds left, extrapolated from the time taken so far.
+    fn eta(&self) -> Option<f64> {
+        let fraction = self.fraction.filter(|f| *f > 0.0 && *f < 1.0)?;
+        (self.state == State::Encoding)
+            .then(|| self.elapsed().as_secs_f64() * (1.0 - fraction) / fraction)
+    }
+}
+
+struct Server {
+    started_at: Instant,
+    runs: Mutex<Vec<Run>>,
+}
+
+/// Reports one run to the status server.
+pub struct Monitor {
+    server: Arc<Server>,
+    index: usize,
+}
+
+/// Add a run to the status server, starting the server on `addr` if it is
+/// not running yet.
+pub fn register(addr: SocketAddr, args: &FfmpegArgs) -> Result<Monitor, Error> {
+    let server = {
+        let mut slot = SERVER.lock().unwrap_or_else(|e| e.into_inner());
+        match *slot {
+            Some(ref server) => Arc::clone(server),
+            None => {
+                let server = start(addr)?;
+                *slot = Some(Arc::clone(&server));
+                server
+            }
+        }
+    };
+
+    let run = Run {
+        state: State::Starting,
+        inputs: args
+            .args
+            .windows(2)
+            .filter(|w| w[0] == "-i")
+            .map(|w| w[1].clone())
+            .collect(),
+        outputs: args.outputs.clone(),
+        started_at: Instant::now(),
+        elapsed: None,
+        stats: ProgressStats::default(),
+        offsets: Offsets::default(),
+        fraction: None,
+        exit_code: None,
+        error: None,
+        log: VecDeque::with_capacity(LOG_LINES),
+    };
+    let index = {
+        let mut runs = server.runs.lock().unwrap_or_else(|e| e.into_inner());
+        runs.push(run);
+        runs.len() - 1
+    };
+    Ok(Monitor { server, index })
+}
+
+fn start(addr: SocketAddr) -> Result<Arc<Server>, Error> {
+    let listener = TcpListener::bind(addr).map_err(Error::ListenFailed)?;
+    let server = Arc::new(Server {
+        started_at: Instant::now(),
+        runs: Mutex::new(Vec::new()),
+    });
+
+    let server_clone = Arc::clone(&server);
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            let server = Arc::clone(&server_clone);
+            thread::spawn(move || handle(stream, &server));
+        }
+    });
+    Ok(server)
+}
+
+impl Monitor {
+    pub fn progress(&self, stats: &ProgressStats, fraction: Option<f64>) {
+        self.update(|run| {
+            run.state = State::Encoding;
+            // A retry or the second pass counts from zero again.
+            for (offset, last, now) in [
+                (&mut run.offsets.frames, run.stats.frame, stats.frame),
+                (
+                    &mut run.offsets.dup_frames,
+                    run.stats.dup_frames,
+                    stats.dup_frames,
+                ),
+                (
+                    &mut run.offsets.drop_frames,
+                    run.stats.drop_frames,
+                    stats.drop_frames,
+                ),
+            ] {
+                if now < last {
+                    *offset += last;
+                }
+            }
+            run.stats = stats.clone();
+            if fraction.is_some() {
+                run.fraction = fraction;
+            }
+        });
+    }
+
+    pub fn log(&self, line: &str) {
+        self.update(|run| {
+            if run.log.len() == LOG_LINES {
+                run.log.pop_front();
+            }
+            run.log.push_back(line.to_string());
+        });
+    }
+
+    pub fn finish(&self, result: &Result<Attempt, Error>) {
+        self.update(|run| {
+            run.elapsed = Some(run.started_at.elapsed());
+            match result {
+                Ok(attempt) => {
+                    run.exit_code = Some(attempt.code);
+                    run.state = if attempt.code == 0 {
+                        State::Finished
+                    } else {
+                        State::Failed
+                    };
+                    if attempt.code == 0 {
+                        run.fraction = Some(1.0);
+                    }
+                }
+                Err(e) => {
+                    run.exit_code = Some(e.exit_code());
+                    run.error = Some(e.to_string());
+                    run.state = State::Failed;
+                }
+            }
+        });
+    }
+
+    fn update(&self, f: impl FnOnce(&mut Run)) {
+        if let Ok(mut runs) = self.server.runs.lock()
+            && let Some(run) = runs.get_mut(self.index)
+        {
+            f(run);
+        }
+    }
+}
+
+fn handle(mut stream: TcpStream, server: &Server) {
+    let _ = stream.set_read_timeout(Some(REQUEST_TIMEOUT));
+    let _ = stream.set_write_timeout(Some(REQUEST_TIMEOUT));
+    let Some((method, path)) = http::read_request(&mut stream) else {
+        return;
+    };
+    if method != "GET" {
+        http::respond(
+            &mut stream,
+            "405 Method Not Allowed",
+            "text/plain",
+            "method not allowed\n",
+        );
+        return;
+    }
+
+    let Ok(runs) = server.runs.lock() else { return };
+    match path.as_str() {
+        "/" => http::respond(
+            &mut stream,
+            "200 OK",
+            "text/html; charset=utf-8",
+            &html_page(&runs),
+        ),
+        "/status" => http::respond(
+            &mut stream,
+            "200 OK",
+            "application/json",
+            &status_json(server, &runs),
+        ),
+        "/metrics" => http::respond(
+            &mut stream,
+            "200 OK",
+            "text/plain; version=0.0.4; charset=utf-8",
+            &metrics(&runs),
+        ),
+        _ => http::respond(&mut stream, "404 Not Found", "text/plain", "not found\n"),
+    }
+}
+
+fn status_json(server: &Server, runs: &[Run]) -> String {
+    let runs = runs
+        .iter()
+        .map(|run| {
+            let stats = &run.stats;
+            let mut object = Object::new()
+                .str("state", run.state.name())
+                .raw(
+                    "inputs",
+                    &json::string_array(run.inputs.iter().map(String::as_str)),
+                )
+                .raw(
+                    "outputs",
+                    &json::string_array(run.outputs.iter().map(String::as_str)),
+                )
+                .num("elapsed", run.elapsed().as_secs_f64())
+                .opt_num("percent", run.fraction.map(|f| (f * 1000.0).round() / 10.0))
+                .opt_num("eta", run.eta().map(|eta| eta.round()))
+                .int("frame", stats.frame as i64)
+                .num("fps", stats.fps)
+                .num("speed", stats.speed)
+                .opt_num("bitrate_kbps", stats.bitrate_kbps)
+                .int("total_size", stats.total_size as i64)
+                .int("out_time_us", stats.out_time_us as i64)
+                .int("dup_frames", stats.dup_frames as i64)
+                .int("drop_frames", stats.drop_frames as i64);
+            object = match run.exit_code {
+                Some(code) => object.int("exit_code", code as i64),
+                None => object.raw("exit_code", "null"),
+            };
+            if let Some(ref error) = run.error {
+                object = object.str("error", error);
+            }
+            object
+                .raw(
+                    "log",
+                    &json::string_array(run.log.iter().map(String::as_str)),
+                )
+                .finish()
+        })
+        .collect::<Vec<_>>();
+
+    Object::new()
+        .int("pid", process::id() as i64)
+        .num("uptime", server.started_at.elapsed().as_secs_f64())
+        .raw("runs", &format!("[{}]", runs.join(",")))
+        .finish()
+}
+
+fn html_page(runs: &[Run]) -> String {
+    let mut page = String::new();
+    let _ = write!(
+        page,
+        "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\">\
+         <meta http-equiv=\"refresh\" content=\"{PAGE_REFRESH}\"><title>ffpb</title>\
+         <style>body{{font-family:sans-serif;margin:2em;color:#111827}}\
+         .bar{{background:#e5e7eb;height:1em;border-radius:.5em;overflow:hidden}}\
+         .fill{{background:linear-gradient(90deg,#a855f7,#ec4899);height:100%}}\
+         .finished .fill{{background:#4bb543}}.failed .fill{{background:#ef4444}}\
+         pre{{background:#f3f4f6;padding:.5em;overflow-x:auto}}\
+         dt{{font-weight:bold;float:left;clear:left;width:7em}}</style></head><body>\n"
+    );
+    if runs.is_empty() {
+        page.push_str("<p>Waiting for ffmpeg…</p>\n");
+    }
+
+    for run in runs {
+        let stats = &run.stats;
+        let percent = run.fraction.unwrap_or(0.0) * 100.0;
+        let _ = write!(
+            page,
+            "<section class=\"{}\"><h2>{} → {}</h2>\
+             <div class=\"bar\"><div class=\"fill\" style=\"width:{percent:.1}%\"></div></div>\n<dl>",
+            run.state.name(),
+            escape(&run.inputs.join(", ")),
+            escape(&run.outputs.join(", ")),
+        );
+
+        let mut row = |name: &str, value: &str| {
+            let _ = write!(page, "<dt>{name}</dt><dd>{}</dd>", escape(value));
+        };
+        row("state", run.state.name());
+        if run.fraction.is_some() {
+            row("progress", &format!("{percent:.1}%"));
+        }
+        row(
+            "elapsed",
+            &format_time_clock(run.elapsed().as_micros() as u64),
+        );
+        if let Some(eta) = run.eta() {
+            row("eta", &format_time_clock((eta * 1_000_000.0) as u64));
+        }
+        row("time", &format_time_clock(stats.out_time_us));
+        row("frame", &stats.frame.to_string());
+        row("fps", &format!("{:.1}", stats.fps));
+        row("speed", &format!("{:.2}x", stats.speed));
+        if let Some(bitrate) = stats.bitrate_kbps {
+            row("bitrate", &format!("{bitrate:.1} kbit/s"));
+        }
+        row("size", &format_size(stats.total_size));
+        if stats.dup_frames > 0 || stats.drop_frames > 0 {
+            row(
+                "dup / drop",
+                &format!("{} / {}", stats.dup_frames, stats.drop_frames),
+            );
+        }
+        if let Some(code) = run.exit_code {
+            row("exit code", &code.to_string());
+        }
+        if let Some(ref error) = run.error {
+            row("error", error);
+        }
+        page.push_str("</dl>\n");
+
+        if !run.log.is_empty() {
+            let log = run.log.iter().cloned().collect::<Vec<_>>().join("\n");
+            let _ = writeln!(page, "<pre>{}</pre>", escape(&log));
+        }
+        page.push_str("</section>\n");
+    }
+
+    page.push_str("</body></html>\n");
+    page
+}
+
+/// Metrics in the Prometheus text format. Every run is labelled with its
+/// index and first output.
+fn metrics(runs: &[Run]) -> String {
+    let labels = runs
+        .iter()
+        .enumerate()
+        .map(|(index, run)| {
+            format!(
+                "run=\"{index}\",output=\"{}\"",
+                escape_label(run.outputs.first().map_or("", String::as_str))
+            )
+        })
+        .collect::<Vec<_>>();
+
+    let mut buf = String::new();
+    let mut family = |name: &str, kind: &str, help: &str, value: &dyn Fn(&Run) -> Option<f64>| {
+        let _ = writeln!(buf, "# HELP {name} {help}");
+        let _ = writeln!(buf, "# TYPE {name} {kind}");
+        for (run, labels) in runs.iter().zip(&labels) {
+            if let Some(value) = value(run).filter(|v| v.is_finite()) {
+                let _ = writeln!(buf, "{name}{{{labels}}} {value}");
+            }
+        }
+    };
+
+    family(
+        "ffpb_frames_total",
+        "counter",
+        "Frames encoded over all attempts and passes.",
+        &|run| Some(run.frames() as f64),
+    );
+    family("ffpb_fps", "gauge", "Frames encoded per second.", &|run| {
+        Some(run.stats.fps)
+    });
+    family(
+        "ffpb_speed_ratio",
+        "gauge",
+        "Encoding speed relative to realtime.",
+        &|run| Some(run.stats.speed),
+    );
+    family(
+        "ffpb_bitrate_bits_per_second",
+        "gauge",
+        "Bitrate of the output.",
+        &|run| run.stats.bitrate_kbps.map(|kbps| kbps * 1000.0),
+    );
+    family(
+        "ffpb_output_bytes",
+        "gauge",
+        "Bytes written to the outputs.",
+        &|run| Some(run.stats.total_size as f64),
+    );
+    family(
+        "ffpb_progress_ratio",
+        "gauge",
+        "Part of the input encoded, from 0 to 1.",
+        &|run| run.fraction,
+    );
+    family(
+        "ffpb_dup_frames_total",
+        "counter",
+        "Frames duplicated to keep the frame rate.",
+        &|run| Some(run.dup_frames() as f64),
+    );
+    family(
+        "ffpb_drop_frames_total",
+        "counter",
+        "Frames dropped to keep the frame rate.",
+        &|run| Some(run.drop_frames() as f64),
+    );
+    family(
+        "ffpb_elapsed_seconds",
+        "gauge",
+        "Wall-clock time of the run.",
+        &|run| Some(run.elapsed().as_secs_f64()),
+    );
+    family(
+        "ffpb_exit_code",
+        "gauge",
+        "Exit code of a finished run.",
+        &|run| run.exit_code.map(f64::from),
+    );
+
+    let count = |state| runs.iter().filter(|run| run.state == state).count();
+    let _ = writeln!(buf, "# HELP ffpb_runs Runs by state.");
+    let _ = writeln!(buf, "# TYPE ffpb_runs gauge");
+    for state in [State::Starting, State::Encoding] {
+        let _ = writeln!(
+            buf,
+            "ffpb_runs{{state=\"{}\"}} {}",
+            state.name(),
+            count(state)
+        );
+    }
+    let _ = writeln!(buf, "# HELP ffpb_jobs_total Finished runs by outcome.");
+    let _ = writeln!(buf, "# TYPE ffpb_jobs_total counter");
+    for (outcome, state) in [("success", State::Finished), ("failure", State::Failed)] {
+        let _ = writeln!(
+            buf,
+            "ffpb_jobs_total{{outcome=\"{outcome}\"}} {}",
+            count(state)
+        );
+    }
+    buf
+}
+
+/// Escape a Prometheus label value.
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
@@ -0,0 +1,584 @@
+use crate::progress::{ERROR_COLOR, PB_START, WARN_COLOR, bold, dim, fg, reset};
+use std::collections::VecDeque;
+
+/// Maximum number of suppressed stderr lines kept while encoding.
+const BUFFER_LINES: usize = 1000;
+
+/// How ffmpeg words its error messages, after any `[component @ 0x...]` tag.
+const ERROR_PREFIXES: &[&str] = &[
+    "Error ",
+    "Invalid data found",
+    "Conversion failed",
+    "Unknown encoder",
+    "Unknown decoder",
+    "Unrecognized option",
+];
+
+/// Endings of the system errors ffmpeg prints after a file name.
+const ERROR_SUFFIXES: &[&str] = &[
+    ": No such file or directory",
+    ": Permission denied",
+    ": Invalid argument",
+];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    Info,
+    Warning,
+    Error,
+}
+
+/// Guess the severity of an ffmpeg log line.
+///
+/// Uses the `[level]` tag when ffmpeg runs with `-loglevel level`, and falls
+/// back to ffmpeg's usual wording otherwise.
+pub fn line_level(line: &str) -> Level {
+    for tag in ["[panic]", "[fatal]", "[error]"] {
+        if line.contains(tag) {
+            return Level::Error;
+        }
+    }
+    if line.contains("[warning]") {
+        return Level::Warning;
+    }
+    for tag in ["[info]", "[verbose]", "[debug]", "[trace]"] {
+        if line.contains(tag) {
+            return Level::Info;
+        }
+    }
+
+    let message = strip_components(line);
+    if ERROR_PREFIXES.iter().any(|p| message.starts_with(p))
+        || ERROR_SUFFIXES.iter().any(|s| message.ends_with(s))
+    {
+        return Level::Error;
+    }
+
+    let lower = line.to_ascii_lowercase();
+    if ["warning", "deprecated", "past duration", "non-monotonic"]
+        .iter()
+        .any(|k| lower.contains(k))
+    {
+        Level::Warning
+    } else {
+        Level::Info
+    }
+}
+
+/// The message of a log line without its leading `[component @ 0x...]` tags.
+fn strip_components(line: &str) -> &str {
+    let mut message = line.trim();
+    while let Some(rest) = message.strip_prefix('[')
+        && let Some((_, after)) = rest.split_once("] ")
+    {
+        message = after;
+    }
+    message
+}
+
+/// Whether a log line points at a failure worth retrying, like a dropped
+/// connection, an I/O error or an HTTP 5xx reply.
+pub fn is_transient(line: &str) -> bool {
+    let lower = line.to_ascii_lowercase();
+    [
+        "connection reset",
+        "connection refused",
+        "connection timed out",
+        "operation timed out",
+        "input/output error",
+        "network is unreachable",
+        "temporary failure in name resolution",
+        "server returned 5",
+        "http error 5",
+    ]
+    .iter()
+    .any(|k| lower.contains(k))
+}
+
+/// Index of the output file a line of the `Stream mapping:` section maps a
+/// stream to, e.g. 1 for `  Stream #0:1 -> #1:0 (copy)` or 0 for
+/// `  scale:default -> Stream #0:0 (libx264)`. `None` for filter inputs.
+pub fn mapped_output(line: &str) -> Option<usize> {
+    let (_, target) = line.split_once(" -> ")?;
+    let target = target.trim_start();
+    let target = target.strip_prefix("Stream ").unwrap_or(target);
+    let (file, _) = target.strip_prefix('#')?.split_once(':')?;
+    file.parse().ok()
+}
+
+/// Whether an unterminated line is ffmpeg asking a question on stdin,
+/// e.g. `File 'out.mp4' already exists. Overwrite? [y/N] `.
+pub fn is_prompt(partial: &[u8]) -> bool {
+    partial.ends_with(b"] ") && partial.windows(3).any(|w| w == b"? [")
+}
+
+/// Ring buffer holding the most recent stderr lines suppressed while encoding.
+#[derive(Default)]
+pub struct StderrBuffer {
+    lines: VecDeque<String>,
+    omitted: usize,
+}
+
+impl StderrBuffer {
+    pub fn push(&mut self, line: String) {
+        if self.lines.len() == BUFFER_LINES {
+            self.lines.pop_front();
+            self.omitted += 1;
+        }
+        self.lines.push_back(line);
+    }
+
+    /// Number of lines dropped because the buffer was full.
+    pub fn omitted(&self) -> usize {
+        self.omitted
+    }
+
+    pub fn lines(&self) -> impl Iterator<Item = &str> {
+        self.lines.iter().map(String::as_str)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lines.is_empty()
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Section {
+    Input,
+    Output,
+    Mapping,
+}
+
+/// Turns raw ffmpeg log lines into the lines ffpb prints.
+///
+/// Colors lines by severity, collapses repeated lines and, with `pretty`,
+/// condenses the input/output and stream mapping sections into short tables.
+pub struct StderrRenderer {
+    color: bool,
+    pretty: bool,
+    last_line: Option<String>,
+    repeats: usize,
+    section: Option<Section>,
+}
+
+impl StderrRenderer {
+    pub fn new(color: bool, pretty: bool) -> Self {
+        Self {
+            color,
+            pretty,
+            last_line: None,
+            repeats: 0,
+            section: None,
+        }
+    }
+
+    /// Render one line, returning zero or more lines to print.
+    pub fn line(&mut self, line: &str) -> Vec<String> {
+        if self.last_line.as_deref() == Some(line) && !line.trim().is_empty() {
+            self.repeats += 1;
+            return Vec::new();
+        }
+
+        let mut out = self.finish();
+        self.last_line = Some(line.to_string());
+
+        if self.pretty {
+            if let Some(rendered) = self.pretty_line(line) {
+                out.extend(rendered);
+            }
+        } else {
+            out.push(self.plain_line(line));
+        }
+        out
+    }
+
+    /// Emit anything held back, i.e. the count of a pending repeated line.
+    pub fn finish(&mut self) -> Vec<String> {
+        if self.repeats == 0 {
+            return Vec::new();
+        }
+
+        let mut buf = String::new();
+        self.dim(&mut buf);
+        buf.push_str(&format!("  ↳ repeated {} more times", self.repeats));
+        self.reset(&mut buf);
+        self.repeats = 0;
+        vec![buf]
+    }
+
+    /// Render a question ffmpeg asks on stdin, leaving the cursor after it.
+    pub fn prompt(&self, prompt: &str) -> String {
+        let mut buf = String::new();
+        self.paint(&mut buf, WARN_COLOR);
+        self.bold(&mut buf);
+        buf.push_str("? ");
+        self.reset(&mut buf);
+        self.bold(&mut buf);
+        buf.push_str(prompt.trim_end());
+        self.reset(&mut buf);
+        buf.push(' ');
+        buf
+    }
+
+    fn plain_line(&self, line: &str) -> String {
+        let mut buf = String::with_capacity(line.len() + 32);
+        let mut message = line;
+
+        // Component prefix, e.g. "[libx264 @ 0x55d0c2a4e0c0] "
+        if let Some(rest) = line.strip_prefix('[')
+            && let Some((tag, after)) = rest.split_once("] ")
+        {
+            let (name, addr) = match tag.split_once(" @ ") {
+                Some((name, addr)) => (name, Some(addr)),
+                None => (tag, None),
+            };
+            if !matches!(
+                name,
+                "panic" | "fatal" | "error" | "warning" | "info" | "verbose" | "debug" | "trace"
+            ) {
+                self.paint(&mut buf, PB_START);
+                buf.push('[');
+                buf.push_str(name);
+                if let Some(addr) = addr {
+                    self.reset(&mut buf);
+                    self.dim(&mut buf);
+                    buf.push_str(" @ ");
+                    buf.push_str(addr);
+                    self.reset(&mut buf);
+                    self.paint(&mut buf, PB_START);
+                }
+                buf.push_str("] ");
+                self.reset(&mut buf);
+                message = after;
+            }
+        }
+
+        if line.trim_start().starts_with("Last message repeated") {
+            self.dim(&mut buf);
+            buf.push_str(message);
+            self.reset(&mut buf);
+            return buf;
+        }
+
+        match line_level(line) {
+            Level::Error => {
+                self.paint(&mut buf, ERROR_COLOR);
+                buf.push_str(message);
+                self.reset(&mut buf);
+            }
+            Level::Warning => {
+                self.paint(&mut buf, WARN_COLOR);
+                buf.push_str(message);
+                self.reset(&mut buf);
+            }
+            Level::Info => buf.push_str(message),
+        }
+        buf
+    }
+
+    fn pretty_line(&mut self, line: &str) -> Option<Vec<String>> {
+        let indented = line.starts_with(' ') || line.starts_with('\t');
+
+        if !indented {
+            self.section = None;
+
+            if let Some(rest) = line.strip_prefix("Input #") {
+                self.section = Some(Section::Input);
+                return Some(vec![self.section_header("Input", rest, ", from '")]);
+            }
+            if let Some(rest) = line.strip_prefix("Output #") {
+                self.section = Some(Section::Output);
+                return Some(vec![self.section_header("Output", rest, ", to '")]);
+            }
+            if line.starts_with("Stream mapping:") {
+                self.section = Some(Section::Mapping);
+                let mut buf = String::new();
+                self.bold(&mut buf);
+                buf.push_str("Stream mapping");
+                self.reset(&mut buf);
+                return Some(vec![buf]);
+            }
+            if line.starts_with("Press [q] to stop") {
+                return None;
+            }
+            return Some(vec![self.plain_line(line)]);
+        }
+
+        let trimmed = line.trim();
+        match self.section {
+            Some(Section::Input | Section::Output) => {
+                if let Some(rest) = trimmed.strip_prefix("Duration: ") {
+                    let mut parts = rest.split(", ");
+                    let duration = parts.next().unwrap_or_default();
+                    let bitrate = parts.find_map(|p| p.strip_prefix("bitrate: "));
+
+                    let mut buf = String::from("  ");
+                    self.dim(&mut buf);
+                    buf.push_str("duration ");
+                    self.reset(&mut buf);
+                    buf.push_str(duration);
+                    if let Some(bitrate) = bitrate {
+                        self.dim(&mut buf);
+                        buf.push_str(" • ");
+                        self.reset(&mut buf);
+                        buf.push_str(bitrate);
+                    }
+                    return Some(vec![buf]);
+                }
+                if let Some(rest) = trimmed.strip_prefix("Stream ")
+                    && let Some((id, rest)) = rest.split_once(": ")
+                {
+                    let (kind, details) = rest.split_once(": ").unwrap_or((rest, ""));
+                    let details = split_top_level(details)
+                        .into_iter()
+                        .take(4)
+                        .collect::<Vec<_>>()
+                        .join(", ");
+
+                    let mut buf = String::from("  ");
+                    self.paint(&mut buf, PB_START);
+                    buf.push_str(&format!("{id:<10}"));
+                    self.reset(&mut buf);
+                    buf.push_str(&format!(" {kind:<9} {details}"));
+                    return Some(vec![buf]);
+                }
+                // Metadata, side data, chapters, ...
+                None
+            }
+            Some(Section::Mapping) => {
+                if let Some(rest) = trimmed.strip_prefix("Stream ") {
+                    let (mapping, codecs) = rest.split_once(" (").unwrap_or((rest, ""));
+                    let codecs = codecs.strip_suffix(')').unwrap_or(codecs);
+
+                    let mut buf = String::from("  ");
+                    self.paint(&mut buf, PB_START);
+                    buf.push_str(&format!("{:<16}", mapping.replace(" -> ", " → ")));
+                    self.reset(&mut buf);
+                    buf.push(' ');
+                    buf.push_str(&codecs.replace(" -> ", " → "));
+                    return Some(vec![buf]);
+                }
+                None
+            }
+            None => Some(vec![self.plain_line(line)]),
+        }
+    }
+
+    /// e.g. `0, matroska,webm, from 'in.mkv':` -> `Input #0 in.mkv (matroska,webm)`
+    fn section_header(&self, label: &str, rest: &str, marker: &str) -> String {
+        let (index, rest) = rest.split_once(", ").unwrap_or((rest, ""));
+        let (format, url) = rest.split_once(marker).unwrap_or((rest, ""));
+        let url = url.strip_suffix("':").unwrap_or(url);
+
+        let mut buf = String::new();
+        self.bold(&mut buf);
+        buf.push_str(&format!("{label} #{index} "));
+        self.reset(&mut buf);
+        self.paint(&mut buf, PB_START);
+        buf.push_str(url);
+        self.reset(&mut buf);
+        self.dim(&mut buf);
+        buf.push_str(&format!(" ({format})"));
+        self.reset(&mut buf);
+        buf
+    }
+
+    fn paint(&self, buf: &mut String, color: (u8, u8, u8)) {
+        if self.color {
+            fg(buf, color.0, color.1, color.2);
+        }
+    }
+
+    fn bold(&self, buf: &mut String) {
+        if self.color {
+            bold(buf);
+        }
+    }
+
+    fn dim(&self, buf: &mut String) {
+        if self.color {
+            dim(buf);
+        }
+    }
+
+    fn reset(&self, buf: &mut String) {
+        if self.color {
+            reset(buf);
+        }
+    }
+}
+
+/// Split on ", " outside of parentheses and brackets.
+fn split_top_level(s: &str) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut depth = 0i32;
+    let mut start = 0;
+
+    for (i, c) in s.char_indices() {
+        match c {
+            '(' | '[' => depth += 1,
+            ')' | ']' => depth -= 1,
+            ',' if depth == 0 => {
+                parts.push(s[start..i].trim());
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    if start < s.len() {
+        parts.push(s[start..].trim());
+    }
+    parts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn classifies_lines() {
+        let cases = [
+            ("[error] Conversion failed!", Level::Error),
+            ("[h264 @ 0x1] [fatal] broken", Level::Error),
+            ("[warning] Guessed Channel Layout", Level::Warning),
+            ("[info] Stream #0:0: Video: h264", Level::Info),
+            ("[debug] an error in the word", Level::Info),
+            (
+                "[in#0 @ 0x1] Error opening input: Invalid argument",
+                Level::Error,
+            ),
+            (
+                "Error while decoding stream #0:1: Invalid data",
+                Level::Error,
+            ),
+            (
+                "[mov @ 0x1] Invalid data found when processing input",
+                Level::Error,
+            ),
+            ("Conversion failed!", Level::Error),
+            ("Unrecognized option 'foo'.", Level::Error),
+            ("in.mkv: No such file or directory", Level::Error),
+            (
+                "[mp4 @ 0x1] Non-monotonic DTS; previous: 10",
+                Level::Warning,
+            ),
+            ("Past duration 0.99 too large", Level::Warning),
+            ("title           : error_recovery_failed.mkv", Level::Info),
+            ("Output #0, mp4, to 'cannot-invalid.mp4':", Level::Info),
+            (
+                "  encoder         : Lavc with error resilience",
+                Level::Info,
+            ),
+        ];
+        for (line, level) in cases {
+            assert_eq!(line_level(line), level, "{line}");
+        }
+    }
+
+    #[test]
+    fn strips_component_tags() {
+        assert_eq!(strip_components("[a @ 0x1] [b] message"), "message");
+        assert_eq!(strip_components("  message "), "message");
+        assert_eq!(strip_components("[unclosed message"), "[unclosed message");
+    }
+
+    #[test]
+    fn detects_transient_failures() {
+        assert!(is_transient("[tcp @ 0x1] Connection reset by peer"));
+        assert!(is_transient("Server returned 503 Service Unavailable"));
+        assert!(is_transient("out.mp4: Input/output error"));
+        assert!(!is_transient("Server returned 404 Not Found"));
+        assert!(!is_transient("in.mkv: No such file or directory"));
+    }
+
+    #[test]
+    fn finds_mapped_outputs() {
+        assert_eq!(mapped_output("  Stream #0:1 -> #1:0 (copy)"), Some(1));
+        assert_eq!(
+            mapped_output("  scale:default -> Stream #0:0 (libx264)"),
+            Some(0)
+        );
+        assert_eq!(mapped_output("  Stream #0:0 (h264) -> scale:default"), None);
+        assert_eq!(mapped_output("Stream mapping:"), None);
+    }
+
+    #[test]
+    fn detects_prompts() {
+        assert!(is_prompt(b"File 'a.mp4' already exists. Overwrite? [y/N] "));
+        assert!(!is_prompt(b"File 'a.mp4' already exists. Overwrite? [y/N]"));
+        assert!(!is_prompt(b"[libx264 @ 0x1] "));
+    }
+
+    #[test]
+    fn keeps_the_latest_lines() {
+        let mut buffer = StderrBuffer::default();
+        assert!(buffer.is_empty());
+        for i in 0..BUFFER_LINES + 2 {
+            buffer.push(i.to_string());
+        }
+        assert_eq!(buffer.omitted(), 2);
+        assert_eq!(buffer.lines().count(), BUFFER_LINES);
+        assert_eq!(buffer.lines().next(), Some("2"));
+    }
+
+    #[test]
+    fn collapses_repeated_lines() {
+        let mut renderer = StderrRenderer::new(false, false);
+        assert_eq!(
+            renderer.line("[aac @ 0x1] Too many bits"),
+            ["[aac @ 0x1] Too many bits"]
+        );
+        assert!(renderer.line("[aac @ 0x1] Too many bits").is_empty());
+        assert!(renderer.line("[aac @ 0x1] Too many bits").is_empty());
+        assert_eq!(renderer.line("done"), ["  ↳ repeated 2 more times", "done"]);
+        assert!(renderer.finish().is_empty());
+    }
+
+    #[test]
+    fn condenses_sections() {
+        let mut renderer = StderrRenderer::new(false, true);
+        let lines = [
+            "Input #0, matroska,webm, from 'in.mkv':",
+            "  Metadata:",
+            "    title           : Test",
+            "  Duration: 00:00:05.00, start: 0.000000, bitrate: 2450 kb/s",
+            "  Stream #0:0(eng): Video: h264 (High), yuv420p(tv, bt709), 1920x1080, 23.98 fps, 1k tbn",
+            "Stream mapping:",
+            "  Stream #0:0 -> #0:0 (h264 (native) -> h264 (libx264))",
+            "Press [q] to stop, [?] for help",
+        ];
+        let out: Vec<_> = lines.iter().flat_map(|l| renderer.line(l)).collect();
+        assert_eq!(
+            out,
+            [
+                "Input #0 in.mkv (matroska,webm)",
+                "  duration 00:00:05.00 • 2450 kb/s",
+                "  #0:0(eng)  Video     h264 (High), yuv420p(tv, bt709), 1920x1080, 23.98 fps",
+                "Stream mapping",
+                "  #0:0 → #0:0      h264 (native) → h264 (libx264)",
+            ]
+        );
+    }
+
+    #[test]
+    fn renders_prompts() {
+        let renderer = StderrRenderer::new(false, false);
+        assert_eq!(
+            renderer.prompt("Overwrite? [y/N] \n"),
+            "? Overwrite? [y/N] "
+        );
+    }
+
+    #[test]
+    fn splits_outside_parentheses() {
+        assert_eq!(
+            split_top_level("h264 (High), yuv420p(tv, bt709), 1920x1080 [SAR 1:1, DAR 16:9]"),
+            [
+                "h264 (High)",
+                "yuv420p(tv, bt709)",
+                "1920x1080 [SAR 1:1, DAR 16:9]"
+            ]
+        );
+        assert!(split_top_level("").is_empty());
+    }
+}
@@ -0,0 +1,86 @@
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+    net::TcpStream,
+    path::PathBuf,
+};
+
+/// Where a `-progress` given by the user should receive the progress lines.
+///
+/// ffpb always reads the progress from ffmpeg itself and copies every line
+/// to this target.
+#[derive(Clone)]
+pub enum ProgressTarget {
+    Stdout,
+    Stderr,
+    File(PathBuf),
+    /// `host:port` of a `tcp://` url.
+    Tcp(String),
+    #[cfg(unix)]
+    Unix(PathBuf),
+}
+
+impl ProgressTarget {
+    /// Parse the url given to `-progress`, as far as ffpb can write to it.
+    pub fn parse(url: &str) -> Option<Self> {
+        if let Some(fd) = url.strip_prefix("pipe:") {
+            return match fd {
+                "" | "1" => Some(Self::Stdout),
+                "2" => Some(Self::Stderr),
+                _ => None,
+            };
+        }
+        if url == "-" {
+            return Some(Self::Stdout);
+        }
+        if let Some(path) = url.strip_prefix("file:") {
+            return (!path.is_empty()).then(|| Self::File(PathBuf::from(path)));
+        }
+        if let Some(rest) = url.strip_prefix("tcp://") {
+            // Options such as `?timeout=` are not supported.
+            let addr = rest.split(['/', '?']).next().unwrap_or_default();
+            return addr.contains(':').then(|| Self::Tcp(addr.to_string()));
+        }
+        #[cfg(unix)]
+        if let Some(path) = url.strip_prefix("unix:") {
+            let path = path.strip_prefix("//").unwrap_or(path);
+            return (!path.is_empty()).then(|| Self::Unix(PathBuf::from(path)));
+        }
+        if url.contains("://") {
+            return None;
+        }
+        Some(Self::File(PathBuf::from(url)))
+    }
+
+    pub fn open(&self) -> io::Result<ProgressTee> {
+        let writer: Box<dyn Write + Send> = match self {
+            Self::Stdout => Box::new(io::stdout()),
+            Self::Stderr => Box::new(io::stderr()),
+            Self::File(path) => Box::new(File::create(path)?),
+            Self::Tcp(addr) => Box::new(TcpStream::connect(addr)?),
+            #[cfg(unix)]
+            Self::Unix(path) => Box::new(std::os::unix::net::UnixStream::connect(path)?),
+        };
+        Ok(ProgressTee {
+            writer: BufWriter::new(writer),
+        })
+    }
+}
+
+/// Copies progress lines to a [`ProgressTarget`].
+pub struct ProgressTee {
+    writer: BufWriter<Box<dyn Write + Send>>,
+}
+
+impl ProgressTee {
+    /// Write one `key=value` line. Output is flushed at the end of every
+    /// block, like ffmpeg does.
+    pub fn line(&mut self, line: &str) -> io::Result<()> {
+        self.writer.write_all(line.as_bytes())?;
+        self.writer.write_all(b"\n")?;
+        if line.starts_with("progress=") {
+            self.writer.flush()?;
+        }
+        Ok(())
+    }
+}
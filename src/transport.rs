@@ -0,0 +1,297 @@
+use crate::args::FfmpegArgs;
+use std::{
+    io::{self, Read},
+    net::{Ipv4Addr, TcpListener},
+    process::{Child, Command, Stdio},
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+    thread,
+    time::Duration,
+};
+
+/// How often a listener checks for ffmpeg connecting.
+const ACCEPT_POLL: Duration = Duration::from_millis(50);
+
+/// How ffmpeg's `-progress` output reaches ffpb.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Transport {
+    /// ffmpeg's stdout, which then can't carry media.
+    Stdout,
+    /// An extra pipe inherited by ffmpeg, written as `-progress pipe:<fd>`.
+    #[cfg(unix)]
+    Pipe,
+    /// A Unix domain socket in the temporary directory which ffmpeg connects to.
+    #[cfg(unix)]
+    Unix,
+    /// A listener on a local port which ffmpeg connects to.
+    Tcp,
+}
+
+impl Transport {
+    /// Parse the value of `--progress-via`.
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "stdout" => Some(Self::Stdout),
+            #[cfg(unix)]
+            "pipe" => Some(Self::Pipe),
+            #[cfg(unix)]
+            "unix" => Some(Self::Unix),
+            "tcp" => Some(Self::Tcp),
+            _ => None,
+        }
+    }
+
+    /// What the `-progress` url of this transport looks like, with the part
+    /// only known once ffpb opened it as a placeholder.
+    pub fn placeholder_url(self) -> &'static str {
+        match self {
+            Self::Stdout => "pipe:1",
+            #[cfg(unix)]
+            Self::Pipe => "pipe:<fd>",
+            #[cfg(unix)]
+            Self::Unix => "unix:<socket>",
+            Self::Tcp => "tcp://127.0.0.1:<port>",
+        }
+    }
+
+    /// `--progress-via`, or a pipe where ffmpeg can inherit one and a local
+    /// port elsewhere. Both leave ffmpeg's stdout to the user.
+    pub fn for_args(args: &FfmpegArgs) -> Self {
+        if let Some(transport) = args.progress_via {
+            return transport;
+        }
+        #[cfg(unix)]
+        {
+            Self::Pipe
+        }
+        #[cfg(not(unix))]
+        {
+            Self::Tcp
+        }
+    }
+}
+
+/// A transport set up for one ffmpeg run.
+pub struct ProgressSource {
+    url: String,
+    kind: Kind,
+    link: Arc<Link>,
+}
+
+enum Kind {
+    Stdout,
+    #[cfg(unix)]
+    Pipe(io::PipeReader, io::PipeWriter),
+    Listener(Listener),
+}
+
+/// State shared between the thread reading the progress and the run.
+#[derive(Default)]
+pub struct Link {
+    connected: AtomicBool,
+    abandoned: AtomicBool,
+}
+
+impl Link {
+    /// Whether ffmpeg connected, or always for transports it can't miss.
+    pub fn connected(&self) -> bool {
+        self.connected.load(Ordering::SeqCst)
+    }
+
+    /// Stop waiting for ffmpeg to connect, e.g. because it already exited.
+    pub fn abandon(&self) {
+        self.abandoned.store(true, Ordering::SeqCst);
+    }
+}
+
+impl ProgressSource {
+    pub fn open(transport: Transport) -> io::Result<Self> {
+        let (url, kind) = match transport {
+            Transport::Stdout => ("pipe:1".to_string(), Kind::Stdout),
+            #[cfg(unix)]
+            Transport::Pipe => {
+                use std::os::fd::AsRawFd;
+                let (reader, writer) = io::pipe()?;
+                (
+                    format!("pipe:{}", writer.as_raw_fd()),
+                    Kind::Pipe(reader, writer),
+                )
+            }
+            #[cfg(unix)]
+            Transport::Unix => {
+                let socket = SocketFile::new();
+                let listener = std::os::unix::net::UnixListener::bind(&socket.0)?;
+                listener.set_nonblocking(true)?;
+                let url = format!("unix:{}", socket.0.display());
+                (
+                    url,
+                    Kind::Listener(Listener::Unix {
+                        listener,
+                        _socket: socket,
+                    }),
+                )
+            }
+            Transport::Tcp => {
+                let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0))?;
+                listener.set_nonblocking(true)?;
+                let url = format!("tcp://{}", listener.local_addr()?);
+                (url, Kind::Listener(Listener::Tcp(listener)))
+            }
+        };
+        Ok(Self {
+            url,
+            kind,
+            link: Arc::default(),
+        })
+    }
+
+    /// The value to pass to ffmpeg's `-progress`.
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// Set up ffmpeg's stdout, and let ffmpeg inherit the pipe if there is one.
+    /// Unless it carries the progress, stdout goes straight to ffpb's stdout.
+    pub fn configure(&self, command: &mut Command) {
+        match self.kind {
+            Kind::Stdout => {
+                command.stdout(Stdio::piped());
+            }
+            #[cfg(unix)]
+            Kind::Pipe(_, ref writer) => {
+                use std::os::{fd::AsRawFd, unix::process::CommandExt};
+                let fd = writer.as_raw_fd();
+                command.stdout(Stdio::inherit());
+                // SAFETY: fcntl is async-signal-safe. The pipe is created with
+                // close-on-exec, which is cleared for ffmpeg only.
+                unsafe {
+                    command.pre_exec(move || {
+                        if libc::fcntl(fd, libc::F_SETFD, 0) == -1 {
+                            return Err(io::Error::last_os_error());
+                        }
+                        Ok(())
+                    });
+                }
+            }
+            Kind::Listener(_) => {
+                command.stdout(Stdio::inherit());
+            }
+        }
+    }
+
+    /// Called once ffmpeg was spawned. Returns what the progress thread reads
+    /// from, and the link the run uses to check on it.
+    pub fn start(self, child: &mut Child) -> (Connection, Arc<Link>) {
+        let kind = match self.kind {
+            Kind::Stdout => {
+                let stdout = child.stdout.take().expect("stdout should be piped");
+                Pending::Ready(Box::new(stdout))
+            }
+            // Dropping the write end here means the reader sees the end of
+            // the pipe as soon as ffmpeg exits.
+            #[cfg(unix)]
+            Kind::Pipe(reader, _writer) => Pending::Ready(Box::new(reader)),
+            Kind::Listener(listener) => Pending::Listening(listener),
+        };
+        if let Pending::Ready(_) = kind {
+            self.link.connected.store(true, Ordering::SeqCst);
+        }
+        (
+            Connection {
+                kind,
+                link: Arc::clone(&self.link),
+            },
+            self.link,
+        )
+    }
+}
+
+/// A socket ffmpeg connects to, as ffmpeg is always the client.
+enum Listener {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix {
+        listener: std::os::unix::net::UnixListener,
+        /// Removes the socket file along with the listener.
+        _socket: SocketFile,
+    },
+}
+
+impl Listener {
+    fn accept(&self) -> io::Result<Box<dyn Read + Send>> {
+        match self {
+            Listener::Tcp(listener) => {
+                let (stream, _) = listener.accept()?;
+                stream.set_nonblocking(false)?;
+                Ok(Box::new(stream))
+            }
+            #[cfg(unix)]
+            Listener::Unix { listener, .. } => {
+                let (stream, _) = listener.accept()?;
+                stream.set_nonblocking(false)?;
+                Ok(Box::new(stream))
+            }
+        }
+    }
+}
+
+/// Path of a Unix domain socket, removed again when dropped.
+#[cfg(unix)]
+struct SocketFile(std::path::PathBuf);
+
+#[cfg(unix)]
+impl SocketFile {
+    fn new() -> Self {
+        use std::sync::atomic::AtomicUsize;
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+        Self(std::env::temp_dir().join(format!(
+            "ffpb-{}-{}.sock",
+            std::process::id(),
+            COUNTER.fetch_add(1, Ordering::SeqCst)
+        )))
+    }
+}
+
+#[cfg(unix)]
+impl Drop for SocketFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.0);
+    }
+}
+
+/// The progress stream of a spawned ffmpeg, possibly not connected yet.
+pub struct Connection {
+    kind: Pending,
+    link: Arc<Link>,
+}
+
+enum Pending {
+    Ready(Box<dyn Read + Send>),
+    Listening(Listener),
+}
+
+impl Connection {
+    /// Wait for ffmpeg to connect. Returns `None` if the run gave up on it.
+    pub fn wait(self) -> Option<Box<dyn Read + Send>> {
+        match self.kind {
+            Pending::Ready(reader) => Some(reader),
+            Pending::Listening(listener) => loop {
+                match listener.accept() {
+                    Ok(stream) => {
+                        self.link.connected.store(true, Ordering::SeqCst);
+                        return Some(stream);
+                    }
+                    Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                        if self.link.abandoned.load(Ordering::SeqCst) {
+                            return None;
+                        }
+                        thread::sleep(ACCEPT_POLL);
+                    }
+                    Err(_) => return None,
+                }
+            },
+        }
+    }
+}
@@ -0,0 +1,224 @@
+use crate::{
+    Error,
+    args::{FfmpegArgs, output_positions},
+    progress::Stage,
+    runner::{self, Attempt, RunContext},
+};
+use std::{
+    env, fs,
+    path::PathBuf,
+    process,
+    sync::{
+        Arc,
+        atomic::{AtomicUsize, Ordering},
+    },
+    time::Instant,
+};
+
+/// Part of the bar filled by the first pass, which usually runs with faster
+/// settings than the second.
+const PASS1_WEIGHT: f64 = 1.0 / 3.0;
+
+#[cfg(windows)]
+const NULL_OUTPUT: &str = "NUL";
+#[cfg(not(windows))]
+const NULL_OUTPUT: &str = "/dev/null";
+
+/// Distinguishes the passlog files of two-pass encodes running in parallel.
+static PASSLOG_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// The commands of both passes of a two-pass encode.
+pub struct Passes {
+    /// Pass 1, to the null muxer without audio.
+    pub first: FfmpegArgs,
+    pub second: FfmpegArgs,
+    /// Prefix of the passlog files.
+    pub passlog: String,
+    /// Whether ffpb picked the passlog and removes it afterwards.
+    pub temporary: bool,
+}
+
+/// Split a command into both passes, passing `-passlogfile` to each.
+pub fn passes(args: &FfmpegArgs) -> Result<Passes, Error> {
+    let positions = output_positions(&args.args);
+    let &[output] = positions.as_slice() else {
+        return Err(Error::InvalidOption(
+            "--two-pass needs exactly one output".into(),
+        ));
+    };
+    if args
+        .args
+        .iter()
+        .any(|a| a == "-pass" || a.starts_with("-pass:"))
+    {
+        return Err(Error::InvalidOption(
+            "--two-pass runs both passes itself, remove -pass".into(),
+        ));
+    }
+
+    let user_passlog = args
+        .args
+        .windows(2)
+        .find(|w| w[0] == "-passlogfile")
+        .map(|w| w[1].clone());
+    let temporary = user_passlog.is_none();
+    let passlog = user_passlog.unwrap_or_else(|| {
+        env::temp_dir()
+            .join(format!(
+                "ffpb-{}-{}",
+                process::id(),
+                PASSLOG_COUNTER.fetch_add(1, Ordering::SeqCst)
+            ))
+            .to_string_lossy()
+            .to_string()
+    });
+
+    let mut first = args.clone();
+    first.args.splice(
+        output..=output,
+        [
+            "-pass",
+            "1",
+            "-passlogfile",
+            &passlog,
+            "-an",
+            "-f",
+            "null",
+            NULL_OUTPUT,
+        ]
+        .map(String::from),
+    );
+    first.outputs = vec![NULL_OUTPUT.to_string()];
+    first.per_output = false;
+
+    let mut second = args.clone();
+    second.args.splice(
+        output..output,
+        ["-pass", "2", "-passlogfile", &passlog].map(String::from),
+    );
+
+    Ok(Passes {
+        first,
+        second,
+        passlog,
+        temporary,
+    })
+}
+
+/// Run a two-pass encode: pass 1 to the null muxer without audio, then pass 2
+/// to the real output, shown as a single bar.
+///
+/// The passlog files are written to the temporary directory and removed
+/// afterwards, unless `-passlogfile` is given.
+pub fn run(args: &FfmpegArgs, ctx: &RunContext) -> Result<Attempt, Error> {
+    let Passes {
+        first,
+        second,
+        passlog,
+        temporary,
+    } = passes(args)?;
+
+    let started_at = Instant::now();
+    let paused = Arc::default();
+    let stage = |pass: u8| Stage {
+        label: format!("pass {pass}/2"),
+        start: if pass == 1 { 0.0 } else { PASS1_WEIGHT },
+        weight: if pass == 1 {
+            PASS1_WEIGHT
+        } else {
+            1.0 - PASS1_WEIGHT
+        },
+        started_at,
+        paused: Arc::clone(&paused),
+        last: pass == 2,
+    };
+
+    let result = runner::run_with_retries(
+        &first,
+        &RunContext {
+            stage: Some(stage(1)),
+            ..ctx.clone()
+        },
+    )
+    .and_then(|first| {
+        if first.code != 0 {
+            return Ok(first);
+        }
+        runner::run_with_retries(
+            &second,
+            &RunContext {
+                stage: Some(stage(2)),
+                ..ctx.clone()
+            },
+        )
+    });
+
+    if temporary {
+        remove_passlogs(&passlog);
+    }
+    result
+}
+
+/// Remove the files ffmpeg and the encoders wrote next to the passlog prefix,
+/// e.g. `<prefix>-0.log` and `<prefix>-0.log.mbtree`.
+fn remove_passlogs(prefix: &str) {
+    let prefix = PathBuf::from(prefix);
+    let (Some(dir), Some(name)) = (prefix.parent(), prefix.file_name()) else {
+        return;
+    };
+    // Only `<prefix>-<n>.log*`, as `ffpb-<pid>-1` is also the start of the
+    // prefix `ffpb-<pid>-10` of another job in the same batch.
+    let stream_prefix = format!("{}-", name.to_string_lossy());
+
+    for entry in fs::read_dir(dir).into_iter().flatten().flatten() {
+        let file_name = entry.file_name().to_string_lossy().to_string();
+        let Some((stream, _)) = file_name
+            .strip_prefix(&stream_prefix)
+            .and_then(|rest| rest.split_once(".log"))
+        else {
+            continue;
+        };
+        if !stream.is_empty() && stream.bytes().all(|b| b.is_ascii_digit()) {
+            let _ = fs::remove_file(entry.path());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn removes_only_own_passlogs() {
+        let dir = env::temp_dir().join(format!("ffpb-passlog-test-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let names = [
+            "ffpb-9-1-0.log",
+            "ffpb-9-1-0.log.mbtree",
+            "ffpb-9-1-1.log.cutree",
+            "ffpb-9-10-0.log",
+            "ffpb-9-10-0.log.mbtree",
+            "ffpb-9-1-notes.log",
+        ];
+        for name in names {
+            fs::write(dir.join(name), "").unwrap();
+        }
+
+        remove_passlogs(&dir.join("ffpb-9-1").to_string_lossy());
+
+        let mut left = fs::read_dir(&dir)
+            .unwrap()
+            .map(|e| e.unwrap().file_name().to_string_lossy().to_string())
+            .collect::<Vec<_>>();
+        left.sort();
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(
+            left,
+            [
+                "ffpb-9-1-notes.log",
+                "ffpb-9-10-0.log",
+                "ffpb-9-10-0.log.mbtree"
+            ]
+        );
+    }
+}
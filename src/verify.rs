@@ -0,0 +1,154 @@
+use crate::{
+    Error,
+    args::is_local_file,
+    progress::{DONE_COLOR, dim, fg, format_time_clock, reset},
+};
+use std::{collections::BTreeMap, path::Path, process::Command};
+
+/// Allowed difference between the expected and the probed duration.
+const MIN_DURATION_TOLERANCE_US: u64 = 1_000_000;
+const DURATION_TOLERANCE: f64 = 0.01;
+
+/// What ffprobe found in an output file.
+struct Probe {
+    duration_us: Option<u64>,
+    /// Number of streams by codec type, e.g. `video` -> 1.
+    streams: BTreeMap<String, usize>,
+}
+
+/// Check every local output with ffprobe: it must exist and be readable, its
+/// duration must match `expected_us` and it must contain as many streams as
+/// ffmpeg mapped to it.
+///
+/// Returns a summary line for every verified output, or all problems found.
+pub fn verify_outputs(
+    ffprobe: &str,
+    outputs: &[String],
+    expected_us: Option<u64>,
+    mapped_streams: &[usize],
+) -> Result<Vec<String>, Error> {
+    let mut verified = Vec::new();
+    let mut problems = Vec::new();
+
+    for (index, output) in outputs.iter().enumerate() {
+        if !is_local_file(output) {
+            continue;
+        }
+        if !Path::new(output).exists() {
+            problems.push(format!("{output} is missing"));
+            continue;
+        }
+
+        let probe = match probe(ffprobe, output) {
+            Ok(probe) => probe,
+            Err(e) => {
+                problems.push(format!("{output} could not be probed ({e})"));
+                continue;
+            }
+        };
+        let stream_count = probe.streams.values().sum::<usize>();
+        let mut ok = true;
+
+        if let (Some(expected), Some(actual)) = (expected_us, probe.duration_us) {
+            let tolerance =
+                MIN_DURATION_TOLERANCE_US.max((expected as f64 * DURATION_TOLERANCE) as u64);
+            if expected.abs_diff(actual) > tolerance {
+                problems.push(format!(
+                    "{output} is {} long, expected {}",
+                    format_time_clock(actual),
+                    format_time_clock(expected)
+                ));
+                ok = false;
+            }
+        }
+
+        if let Some(&expected) = mapped_streams.get(index)
+            && expected != stream_count
+        {
+            let kinds = probe
+                .streams
+                .iter()
+                .map(|(kind, n)| format!("{n} {kind}"))
+                .collect::<Vec<_>>();
+            problems.push(format!(
+                "{output} has {stream_count} streams ({}), expected {expected}",
+                if kinds.is_empty() {
+                    "none".to_string()
+                } else {
+                    kinds.join(", ")
+                }
+            ));
+            ok = false;
+        }
+
+        if ok {
+            let mut line = String::new();
+            fg(&mut line, DONE_COLOR.0, DONE_COLOR.1, DONE_COLOR.2);
+            line.push_str("✓ verified ");
+            reset(&mut line);
+            line.push_str(output);
+            dim(&mut line);
+            if let Some(duration) = probe.duration_us {
+                line.push_str(&format!(" • {}", format_time_clock(duration)));
+            }
+            line.push_str(&format!(" • {stream_count} streams"));
+            reset(&mut line);
+            verified.push(line);
+        }
+    }
+
+    if problems.is_empty() {
+        Ok(verified)
+    } else {
+        Err(Error::VerifyFailed(problems))
+    }
+}
+
+/// Duration of an input in microseconds, as far as ffprobe can tell.
+pub fn input_duration(ffprobe: &str, input: &str) -> Result<u64, String> {
+    probe(ffprobe, input)?
+        .duration_us
+        .ok_or_else(|| "no duration".to_string())
+}
+
+fn probe(ffprobe: &str, output: &str) -> Result<Probe, String> {
+    let result = Command::new(ffprobe)
+        .args([
+            "-v",
+            "error",
+            "-show_entries",
+            "format=duration:stream=codec_type",
+            "-of",
+            "default=noprint_wrappers=1",
+            output,
+        ])
+        .output()
+        .map_err(|e| format!("failed to run ffprobe: {e}"))?;
+
+    if !result.status.success() {
+        let stderr = String::from_utf8_lossy(&result.stderr);
+        return Err(stderr
+            .lines()
+            .next()
+            .unwrap_or("ffprobe failed")
+            .to_string());
+    }
+
+    let mut probe = Probe {
+        duration_us: None,
+        streams: BTreeMap::new(),
+    };
+    for line in String::from_utf8_lossy(&result.stdout).lines() {
+        match line.split_once('=') {
+            Some(("codec_type", kind)) => *probe.streams.entry(kind.to_string()).or_default() += 1,
+            Some(("duration", value)) => {
+                probe.duration_us = value
+                    .parse::<f64>()
+                    .ok()
+                    .map(|secs| (secs * 1_000_000.0) as u64);
+            }
+            _ => {}
+        }
+    }
+    Ok(probe)
+}
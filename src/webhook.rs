@@ -0,0 +1,378 @@
+use crate::{
+    Error,
+    args::FfmpegArgs,
+    http::{self, Url},
+    json::{self, Object},
+    log::timestamp,
+    progress::ProgressStats,
+    runner::Attempt,
+};
+use std::{
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicUsize, Ordering},
+        mpsc::{self, Receiver, Sender},
+    },
+    thread,
+    time::Duration,
+};
+
+/// Connect, write and read timeout of a single request.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+/// Tries per event before it is dropped.
+const ATTEMPTS: u32 = 3;
+/// Wait before the second try, doubled before the third.
+const RETRY_DELAY: Duration = Duration::from_secs(1);
+/// How long ffpb waits for queued events to be delivered once ffmpeg exited.
+const FLUSH_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Posts JSON events about a run to an http endpoint.
+///
+/// Events are queued and sent one after another by a background thread, so
+/// a slow or unreachable endpoint never holds up the encode.
+pub struct Webhook {
+    queue: Mutex<Option<Sender<String>>>,
+    /// Receives the number of undelivered events and the last error once the
+    /// queue is closed and drained.
+    done: Mutex<Receiver<(usize, String)>>,
+    /// Events queued but not yet delivered or given up on.
+    pending: Arc<AtomicUsize>,
+    /// Send a progress event whenever this many more percent are done.
+    every: f64,
+    next_percent: Mutex<f64>,
+    inputs: Vec<String>,
+    outputs: Vec<String>,
+}
+
+impl Webhook {
+    pub fn start(url: &str, every: f64, args: &FfmpegArgs) -> Result<Self, Error> {
+        Self::with_retry_delay(url, every, args, RETRY_DELAY)
+    }
+
+    fn with_retry_delay(
+        url: &str,
+        every: f64,
+        args: &FfmpegArgs,
+        retry_delay: Duration,
+    ) -> Result<Self, Error> {
+        let url = Url::parse(url)
+            .ok_or_else(|| Error::InvalidOption(format!("invalid webhook url: {url}")))?;
+        let (tx, rx) = mpsc::channel::<String>();
+        let (done_tx, done_rx) = mpsc::channel();
+        let pending = Arc::new(AtomicUsize::new(0));
+
+        let pending_clone = Arc::clone(&pending);
+        thread::spawn(move || {
+            let mut failed = 0;
+            let mut last_error = String::new();
+            for body in rx {
+                if let Err(e) = deliver(&url, &body, retry_delay) {
+                    failed += 1;
+                    last_error = e;
+                }
+                pending_clone.fetch_sub(1, Ordering::SeqCst);
+            }
+            let _ = done_tx.send((failed, last_error));
+        });
+
+        Ok(Self {
+            queue: Mutex::new(Some(tx)),
+            done: Mutex::new(done_rx),
+            pending,
+            every,
+            next_percent: Mutex::new(every),
+            inputs: args
+                .args
+                .windows(2)
+                .filter(|w| w[0] == "-i")
+                .map(|w| w[1].clone())
+                .collect(),
+            outputs: args.outputs.clone(),
+        })
+    }
+
+    pub fn started(&self) {
+        self.send(self.event("started").finish());
+    }
+
+    /// Send a progress event if another `every` percent are done.
+    pub fn progress(&self, stats: &ProgressStats, fraction: Option<f64>) {
+        let Some(fraction) = fraction else { return };
+        let percent = fraction * 100.0;
+        {
+            let Ok(mut next) = self.next_percent.lock() else {
+                return;
+            };
+            if percent < *next || percent >= 100.0 {
+                return;
+            }
+            *next = ((percent / self.every).floor() + 1.0) * self.every;
+        }
+
+        let event = self
+            .event("progress")
+            .num("percent", (percent * 10.0).round() / 10.0)
+            .int("frame", stats.frame as i64)
+            .num("fps", stats.fps)
+            .num("speed", stats.speed)
+            .opt_num("bitrate_kbps", stats.bitrate_kbps)
+            .int("total_size", stats.total_size as i64)
+            .int("out_time_us", stats.out_time_us as i64)
+            .int("dup_frames", stats.dup_frames as i64)
+            .int("drop_frames", stats.drop_frames as i64)
+            .finish();
+        self.send(event);
+    }
+
+    /// Send the `finished` or `failed` event and wait a little for the queue
+    /// to drain. Returns a warning if events could not be delivered.
+    pub fn finish(&self, result: &Result<Attempt, Error>, elapsed: Duration) -> Option<String> {
+        let event = match result {
+            Ok(attempt) => self
+                .event(if attempt.code == 0 {
+                    "finished"
+                } else {
+                    "failed"
+                })
+                .int("exit_code", attempt.code as i64)
+                .int("total_size", attempt.total_size as i64)
+                .int("out_time_us", attempt.out_time_us as i64),
+            Err(e) => self
+                .event("failed")
+                .int("exit_code", e.exit_code() as i64)
+                .str("error", &e.to_string()),
+        };
+        self.send(event.num("elapsed", elapsed.as_secs_f64()).finish());
+
+        // Closing the queue lets the sender thread finish after the last event.
+        if let Ok(mut queue) = self.queue.lock() {
+            queue.take();
+        }
+        let done = self.done.lock().ok()?.recv_timeout(FLUSH_TIMEOUT);
+        match done {
+            Ok((failed, last_error)) => (failed > 0)
+                .then(|| format!("webhook: {failed} events not delivered ({last_error})")),
+            Err(_) => Some(format!(
+                "webhook: gave up on {} undelivered events",
+                self.pending.load(Ordering::SeqCst)
+            )),
+        }
+    }
+
+    fn event(&self, name: &str) -> Object {
+        Object::new()
+            .str("event", name)
+            .str("timestamp", &timestamp())
+            .raw(
+                "inputs",
+                &json::string_array(self.inputs.iter().map(String::as_str)),
+            )
+            .raw(
+                "outputs",
+                &json::string_array(self.outputs.iter().map(String::as_str)),
+            )
+    }
+
+    fn send(&self, body: String) {
+        if let Ok(queue) = self.queue.lock()
+            && let Some(ref tx) = *queue
+        {
+            self.pending.fetch_add(1, Ordering::SeqCst);
+            if tx.send(body).is_err() {
+                self.pending.fetch_sub(1, Ordering::SeqCst);
+            }
+        }
+    }
+}
+
+fn deliver(url: &Url, body: &str, retry_delay: Duration) -> Result<(), String> {
+    let mut last_error = String::new();
+    for attempt in 0..ATTEMPTS {
+        if attempt > 0 {
+            thread::sleep(retry_delay * attempt);
+        }
+        match http::post_json(url, body, REQUEST_TIMEOUT) {
+            Ok(status) if (200..300).contains(&status) => return Ok(()),
+            Ok(status) => last_error = format!("http status {status}"),
+            Err(e) => last_error = e,
+        }
+    }
+    Err(last_error)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::args::ColorMode;
+    use std::{
+        io::{Read, Write},
+        net::TcpListener,
+    };
+
+    /// A local http endpoint. It answers the requests in turn with `replies`,
+    /// the last one repeating, where `None` drops the connection unanswered.
+    /// Every request body is sent to the returned receiver.
+    fn stand_in(replies: Vec<Option<u16>>) -> (String, Receiver<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        let (tx, rx) = mpsc::channel();
+        thread::spawn(move || {
+            for (i, stream) in listener.incoming().enumerate() {
+                let mut stream = stream.unwrap();
+                let body = read_body(&mut stream);
+                let _ = tx.send(body);
+                let reply = replies[i.min(replies.len() - 1)];
+                if let Some(status) = reply {
+                    let _ = write!(
+                        stream,
+                        "HTTP/1.1 {status} OK\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+                    );
+                }
+            }
+        });
+        (url, rx)
+    }
+
+    fn read_body(stream: &mut std::net::TcpStream) -> String {
+        let mut request = Vec::new();
+        let mut buf = [0u8; 4096];
+        loop {
+            let n = stream.read(&mut buf).unwrap();
+            request.extend_from_slice(&buf[..n]);
+            let text = String::from_utf8_lossy(&request).to_string();
+            if let Some((head, body)) = text.split_once("\r\n\r\n") {
+                let length = head
+                    .lines()
+                    .find_map(|l| l.strip_prefix("Content-Length: "))
+                    .and_then(|l| l.trim().parse::<usize>().ok())
+                    .unwrap();
+                if body.len() >= length || n == 0 {
+                    return body.to_string();
+                }
+            }
+        }
+    }
+
+    fn args() -> FfmpegArgs {
+        FfmpegArgs {
+            ffmpeg: "ffmpeg".to_string(),
+            verbose: false,
+            dry_run: false,
+            ss: None,
+            to: None,
+            t: None,
+            clean: false,
+            per_output: false,
+            color: ColorMode::Never,
+            pretty: false,
+            no_keys: false,
+            refresh: Duration::from_secs(1),
+            two_pass: false,
+            verify: false,
+            stall_after: Duration::from_secs(5),
+            stall_timeout: None,
+            timeout: None,
+            log_file: None,
+            log_max_size: None,
+            retries: 0,
+            on_success: None,
+            on_failure: None,
+            on_finish: None,
+            webhook: None,
+            webhook_every: 10.0,
+            listen: None,
+            progress_target: None,
+            progress_via: None,
+            outputs: vec!["out.mp4".to_string()],
+            args: ["-i", "in \"1\".mov", "out.mp4"].map(String::from).to_vec(),
+        }
+    }
+
+    /// Start a webhook which retries without waiting long.
+    fn start(url: &str, every: f64) -> Webhook {
+        Webhook::with_retry_delay(url, every, &args(), Duration::from_millis(10)).unwrap()
+    }
+
+    fn attempt(code: i32) -> Attempt {
+        Attempt {
+            code,
+            transient: None,
+            expected_us: None,
+            mapped_streams: Vec::new(),
+            out_time_us: 4_000_000,
+            total_size: 1024,
+        }
+    }
+
+    fn stats(frame: u64) -> ProgressStats {
+        ProgressStats {
+            frame,
+            ..ProgressStats::default()
+        }
+    }
+
+    #[test]
+    fn posts_lifecycle_events() {
+        let (url, bodies) = stand_in(vec![Some(200)]);
+        let webhook = start(&url, 25.0);
+
+        webhook.started();
+        webhook.progress(&stats(10), Some(0.1));
+        webhook.progress(&stats(30), Some(0.3));
+        webhook.progress(&stats(40), Some(0.4));
+        webhook.progress(&stats(60), Some(0.6));
+        let warning = webhook.finish(&Ok(attempt(0)), Duration::from_secs(2));
+        assert_eq!(warning, None);
+
+        let bodies = bodies.try_iter().collect::<Vec<_>>();
+        assert_eq!(bodies.len(), 4, "{bodies:?}");
+        assert!(bodies[0].starts_with(r#"{"event":"started","timestamp":"#));
+        assert!(bodies[0].ends_with(r#""inputs":["in \"1\".mov"],"outputs":["out.mp4"]}"#));
+        assert!(bodies[1].contains(r#""event":"progress""#));
+        assert!(bodies[1].contains(r#""percent":30,"frame":30,"#));
+        assert!(bodies[2].contains(r#""percent":60,"frame":60,"#));
+        assert!(bodies[3].contains(r#""event":"finished""#));
+        assert!(
+            bodies[3]
+                .ends_with(r#""exit_code":0,"total_size":1024,"out_time_us":4000000,"elapsed":2}"#)
+        );
+    }
+
+    #[test]
+    fn reports_failures() {
+        let (url, bodies) = stand_in(vec![Some(200)]);
+        let webhook = start(&url, 10.0);
+        webhook.finish(&Ok(attempt(1)), Duration::from_secs(1));
+        webhook.finish(&Err(Error::FfmpegNotFound), Duration::from_secs(1));
+
+        let bodies = bodies.try_iter().collect::<Vec<_>>();
+        assert!(bodies[0].contains(r#""event":"failed""#));
+        assert!(bodies[0].contains(r#""exit_code":1,"#));
+        assert_eq!(bodies.len(), 1, "the queue is closed after finish");
+    }
+
+    #[test]
+    fn retries_dropped_connections() {
+        let (url, bodies) = stand_in(vec![None, Some(503), Some(200)]);
+        let webhook = start(&url, 10.0);
+        let warning = webhook.finish(&Ok(attempt(0)), Duration::from_secs(1));
+        assert_eq!(warning, None);
+
+        let bodies = bodies.try_iter().collect::<Vec<_>>();
+        assert_eq!(bodies.len(), ATTEMPTS as usize);
+        assert!(bodies.iter().all(|body| body == &bodies[0]));
+    }
+
+    #[test]
+    fn gives_up_after_all_attempts() {
+        let (url, bodies) = stand_in(vec![None]);
+        let webhook = start(&url, 10.0);
+        webhook.started();
+        let warning = webhook.finish(&Ok(attempt(0)), Duration::from_secs(1));
+        assert_eq!(
+            warning.as_deref(),
+            Some("webhook: 2 events not delivered (invalid response)")
+        );
+        assert_eq!(bodies.try_iter().count(), 2 * ATTEMPTS as usize);
+    }
+}